@@ -1,4 +1,4 @@
-#![recursion_limit = "256"]
+#![recursion_limit = "512"]
 mod voice;
 use yew::prelude::*;
 
@@ -91,29 +91,29 @@ impl Component for Model {
                     </section>
 
                     <div class="card-content">
-                        // <div class = "columns level">
-                        //     <p
-                        //         class="column level-item is-one-fifths"
-                        //         style="min-width:50px; max-width:100px"
-                        //     >
-                        //         {"Note:"}
-                        //     </p>
-                        //     <p
-                        //         id="note_precision"
-                        //         class="column level-item is-one-fifths"
-                        //         style="min-width:50px; max-width:100px"
-                        //     />
-                        //     <p
-                        //         id="note_name"
-                        //         class="column level-item is-one-fifths"
-                        //         style="min-width:50px; max-width:100px"
-                        //     />
-                        //     <p
-                        //     id="frequency"
-                        //     class="column level-item is-one-fifths"
-                        //     style="min-width:50px; max-width:100px"
-                        //     />
-                        // </div>
+                        <div class = "columns level">
+                            <p
+                                class="column level-item is-one-fifths"
+                                style="min-width:50px; max-width:100px"
+                            >
+                                {"Note:"}
+                            </p>
+                            <p
+                                id="note_name"
+                                class="column level-item is-one-fifths"
+                                style="min-width:50px; max-width:100px"
+                            />
+                            <p
+                                id="note_precision"
+                                class="column level-item is-one-fifths"
+                                style="min-width:50px; max-width:100px"
+                            />
+                            <p
+                                id="frequency"
+                                class="column level-item is-one-fifths"
+                                style="min-width:50px; max-width:100px"
+                            />
+                        </div>
 
                         {self.slider("volume", "Volume", "1", "0", "200",
                             "Set the volume of the output. \
@@ -122,6 +122,12 @@ impl Component for Model {
                         {self.slider("pitch", "Pitch", "0.01", "0.5", "2.0",
                             "Change the pitch of the output, without changing playback speed."
                         )}
+                        <div class="columns level">
+                            <label class="column level-item checkbox">
+                                <input id="autotune" type="checkbox"/>
+                                {" Auto-tune to C major, instead of using the pitch slider"}
+                            </label>
+                        </div>
                     </div>
                 </div>
             </div>
@@ -168,10 +174,7 @@ impl Model {
     }
 
     fn is_playing(&self) -> bool {
-        match self.state {
-            State::Playing => true,
-            _ => false,
-        }
+        matches!(self.state, State::Playing)
     }
 }
 
@@ -11,12 +11,10 @@ use fon::{
 };
 use pasts::prelude::*;
 use pitch::{
-   //notes::{frequency_to_approx_note, Note},
-   Frequencer,
-   Resynth,
-   Wavelet,
+   notes::{Note, Scale},
+   AutoTune, FrameContext, Frequencer, FrequencerStream, Resynth, ResynthStream, SpectralEffect,
+   Tuner,
 };
-use std::collections::VecDeque;
 use wasm_bindgen::{JsCast, JsValue};
 use wasm_bindgen_futures::future_to_promise;
 use wavy::{Microphone, MicrophoneId, SpeakerId};
@@ -25,10 +23,11 @@ static RUNNING: AtomicBool = AtomicBool::new(false);
 static INITIALIZED: AtomicBool = AtomicBool::new(false);
 
 struct State {
-   freq: Frequencer,
-   resynth: Resynth,
-   wavelets: VecDeque<Wavelet>,
-   //freq_avg: RunningAvg,
+   freq: FrequencerStream,
+   resynth: ResynthStream,
+   autotune: AutoTune,
+   ctx: FrameContext,
+   tuner: Tuner,
    update_counter: usize,
 }
 
@@ -38,46 +37,61 @@ async fn microphone_task(state: &RefCell<State>, mut mic: Microphone<Ch16>) {
 
    loop {
       let mut sample = mic.record().await;
-      let step_size = state.borrow().freq.step_size();
 
       // Stop the frequencer
       if !RUNNING.load(Ordering::Relaxed) {
          // Pull out the empty data
-         while let Some(_) = sample.stream_sample() {}
+         while sample.stream_sample().is_some() {}
          continue;
       }
 
       while let Some(stream) = sample.stream_sample() {
          let chan = stream.channels()[0];
          buffer.push(chan.to_f64());
+      }
 
-         // If there is enough data in the buffer we process it into a wavelet
-         if buffer.len() >= step_size {
-            let mut state = state.borrow_mut();
-            state.update_counter += 1;
-
-            // Skip processing wavelets if we are getting overflowed
-            if state.wavelets.len() <= 1000 {
-               let mut wv = state.freq.feed_audio(&buffer[..]);
-
-               //let freq = wv.base_freq();
-               //let freq = state.freq_avg.update(freq);
+      let mut state = state.borrow_mut();
+      let wavelets = state.freq.feed(&buffer);
+      buffer.clear();
 
-               // if state.update_counter % 20 == 0 {
-               //    let note = frequency_to_approx_note(freq);
-               //    let (note, _prec) = Note::from_approx(note);
-               //    set_text("note_name", &format!("{:?}", note));
-               //    set_text("frequency", &format!("{:.2}Hz", freq));
-               // }
+      for mut wv in wavelets {
+         state.update_counter += 1;
 
-               // Get the pitch shift
-               let pitch = get_slider_value("pitch").unwrap_or(1.0);
-               wv.pitch_shift(pitch);
+         // Skip processing wavelets if we are getting overflowed
+         if state.resynth.queued() > 1000 {
+            continue;
+         }
 
-               state.wavelets.push_back(wv);
+         let reading = {
+            let state = &mut *state;
+            state.tuner.feed_wavelet(&wv, &state.ctx)
+         };
+         if state.update_counter % 5 == 0 {
+            match reading {
+               Some(reading) => {
+                  set_text("note_name", &reading.note.to_string());
+                  set_text("note_precision", &format!("{:+.0}ct", reading.cents));
+                  set_text("frequency", &format!("{:.2}Hz", reading.frequency));
+               }
+               None => {
+                  set_text("note_name", "-");
+                  set_text("note_precision", "");
+                  set_text("frequency", "");
+               }
             }
-            buffer.clear();
          }
+
+         if get_checkbox_value("autotune").unwrap_or(false) {
+            let state = &mut *state;
+            state.autotune.process(&mut wv, &state.ctx);
+            state.ctx.frame_index += 1;
+         } else {
+            // Get the pitch shift
+            let pitch = get_slider_value("pitch").unwrap_or(1.0);
+            wv.pitch_shift(pitch);
+         }
+
+         state.resynth.push_wavelet(wv);
       }
    }
 }
@@ -92,16 +106,14 @@ async fn speakers_task(state: &RefCell<State>) {
 
       let mut state = state.borrow_mut();
 
-      // get the new wavelet if we have one
-      let wv = state.wavelets.pop_front();
+      // allocate new output
+      let mut output = vec![0.0f64; state.resynth.resynth().step_size()];
 
-      if state.wavelets.len() <= 10 {
-         // allocate new output
-         let mut output = vec![0.0f64; state.resynth.step_size()];
-
-         // do the synthesis
-         state.resynth.pull_audio(&mut output, wv);
+      // do the synthesis, missing samples stay silent
+      state.resynth.pull(&mut output);
 
+      // Drop the audio if we are lagging behind
+      if state.resynth.queued() <= 10 {
          // Get the gain factor
 
          let volume = get_slider_value("volume").unwrap_or(50.0);
@@ -121,11 +133,20 @@ async fn start() {
    let microphone = MicrophoneId::default().connect().unwrap();
    let sample_rate = microphone.sample_rate();
 
+   let mut autotune = AutoTune::new("C4".parse::<Note>().unwrap(), Scale::major());
+   autotune.set_retune_speed(0.05);
+
    let state = RefCell::new(State {
-      freq: Frequencer::new(sample_rate as usize, 4096, 1024).unwrap(),
-      resynth: Resynth::new(sample_rate as usize, 4096, 1024).unwrap(),
-      wavelets: VecDeque::new(),
-      //freq_avg: RunningAvg::with_len(20),
+      freq: FrequencerStream::new(Frequencer::new(sample_rate as usize, 4096, 1024).unwrap()),
+      resynth: ResynthStream::new(Resynth::new(sample_rate as usize, 4096, 1024).unwrap()),
+      autotune,
+      ctx: FrameContext {
+         sample_rate: sample_rate as usize,
+         frame_size: 4096,
+         step_size: 1024,
+         frame_index: 0,
+      },
+      tuner: Tuner::new(sample_rate as usize),
       update_counter: 0,
    });
    // Create speaker and microphone tasks.
@@ -173,39 +194,23 @@ where
    val.parse().ok()
 }
 
-// fn set_text(elem: &str, value: &str) {
-//    let val = web_sys::window()
-//       .unwrap()
-//       .document()
-//       .unwrap()
-//       .get_element_by_id(elem)
-//       .unwrap()
-//       .dyn_into::<web_sys::Node>()
-//       .unwrap();
-
-//    val.set_text_content(Some(value));
-// }
-
-// struct RunningAvg {
-//    len: usize,
-//    vals: VecDeque<f64>,
-// }
-
-// impl RunningAvg {
-//    fn with_len(len: usize) -> Self {
-//       Self {
-//          len,
-//          vals: VecDeque::new(),
-//       }
-//    }
-
-//    fn update(&mut self, val: f64) -> f64 {
-//       self.vals.push_back(val);
-
-//       if self.vals.len() >= self.len {
-//          self.vals.pop_front();
-//       }
-
-//       self.vals.iter().sum::<f64>() / self.len as f64
-//    }
-// }
+fn get_checkbox_value(name: &str) -> Option<bool> {
+   let checked = web_sys::window()?
+      .document()?
+      .get_element_by_id(name)?
+      .dyn_into::<web_sys::HtmlInputElement>()
+      .ok()?
+      .checked();
+
+   Some(checked)
+}
+
+fn set_text(elem: &str, value: &str) {
+   let node = web_sys::window()
+      .and_then(|window| window.document())
+      .and_then(|document| document.get_element_by_id(elem));
+
+   if let Some(node) = node {
+      node.set_text_content(Some(value));
+   }
+}
@@ -1,7 +1,11 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use num_complex::Complex32;
-use pitch::fft::{fft, rfft};
+use num_complex::{Complex32, Complex64};
+use pitch::{
+   fft::{fft, rfft, FftPlan, RealFft},
+   Frequencer, Resynth, Wavelet,
+};
 use rand::Rng;
+use rustfft::FFTplanner;
 
 fn bench_fft(c: &mut Criterion) {
    let mut rng = rand::thread_rng();
@@ -20,5 +24,73 @@ fn bench_fft(c: &mut Criterion) {
    c.bench_function("rfft", |b| b.iter(|| rfft(&test_input)));
 }
 
-criterion_group!(benches, bench_fft);
+fn bench_real_fft(c: &mut Criterion) {
+   let input = (0..4096)
+      .map(|k| f64::sin(k as f64 * 0.01))
+      .collect::<Vec<_>>();
+
+   // The full complex transform, as the frequencer used to do it
+   let complex_fft = FFTplanner::new(false).plan_fft(4096);
+   let mut complex = vec![Complex64::new(0.0, 0.0); 4096];
+   let mut spectrum = vec![Complex64::new(0.0, 0.0); 4096];
+   c.bench_function("complex fft 4096", |b| {
+      b.iter(|| {
+         for (z, x) in complex.iter_mut().zip(input.iter()) {
+            *z = Complex64::new(*x, 0.0);
+         }
+         complex_fft.process(&mut complex, &mut spectrum);
+      })
+   });
+
+   let mut plan = FftPlan::<f64>::new(4096).unwrap();
+   c.bench_function("fft plan 4096", |b| {
+      b.iter(|| {
+         for (z, x) in complex.iter_mut().zip(input.iter()) {
+            *z = Complex64::new(*x, 0.0);
+         }
+         plan.forward(&mut complex).unwrap();
+      })
+   });
+
+   let mut real_fft = RealFft::new(4096).unwrap();
+   let mut spectrum = vec![Complex64::new(0.0, 0.0); 2049];
+   c.bench_function("real fft 4096", |b| {
+      b.iter(|| real_fft.process(&input, &mut spectrum).unwrap())
+   });
+}
+
+fn bench_vocoder(c: &mut Criterion) {
+   let audio = (0..1024)
+      .map(|k| f64::sin(k as f64 * 0.01))
+      .collect::<Vec<_>>();
+
+   let mut freq = Frequencer::new(48000, 4096, 1024).unwrap();
+   let mut wavelet = Wavelet::empty(2048);
+   c.bench_function("frequencer 4096/1024", |b| {
+      b.iter(|| freq.feed_audio_into(&audio, &mut wavelet))
+   });
+
+   // 40 ms frames need a mixed-radix transform
+   let mut freq = Frequencer::new(48000, 1920, 1024).unwrap();
+   let mut wavelet_1920 = Wavelet::empty(960);
+   c.bench_function("frequencer 1920/1024", |b| {
+      b.iter(|| freq.feed_audio_into(&audio, &mut wavelet_1920))
+   });
+
+   // Single precision, e.g. for WebAssembly
+   let audio_f32 = audio.iter().map(|x| *x as f32).collect::<Vec<_>>();
+   let mut freq = Frequencer::<f32>::new(48000, 4096, 1024).unwrap();
+   let mut wavelet_f32 = Wavelet::empty(2048);
+   c.bench_function("frequencer f32 4096/1024", |b| {
+      b.iter(|| freq.feed_audio_into(&audio_f32, &mut wavelet_f32))
+   });
+
+   let mut resynth = Resynth::new(48000, 4096, 1024).unwrap();
+   let mut output = vec![0.0; 1024];
+   c.bench_function("resynth 4096/1024", |b| {
+      b.iter(|| resynth.pull_audio_from(&mut output, Some(&wavelet)))
+   });
+}
+
+criterion_group!(benches, bench_fft, bench_real_fft, bench_vocoder);
 criterion_main!(benches);
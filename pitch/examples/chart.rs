@@ -1,15 +1,14 @@
 use fon::{chan::Channel, mono::Mono64, Sample, Sink};
-use pitch::{Frequencer, Resynth, Wavelet};
+use pitch::{Frequencer, FrequencerStream, Resynth, ResynthStream, Wavelet};
 use plotters::prelude::*;
 use std::{cell::RefCell, rc::Rc};
 use twang::Synth;
 
 struct FreqSinkInner {
-   buf: Vec<f64>,
    current_wavelet: Option<Wavelet>,
    capacity: usize,
-   freq: Frequencer,
-   resynth: Resynth,
+   freq: FrequencerStream,
+   resynth: ResynthStream,
    original: Vec<f64>,
    output: Vec<f64>,
 }
@@ -19,26 +18,23 @@ struct FreqSink(Rc<RefCell<FreqSinkInner>>);
 
 impl Sink<Mono64> for FreqSink {
    fn sample_rate(&self) -> u32 {
-      self.0.borrow().freq.sample_rate() as u32
+      self.0.borrow().freq.frequencer().sample_rate() as u32
    }
 
    fn sink_sample<Z: Sample>(&mut self, sample: Z) {
       let mut cell = self.0.borrow_mut();
-      cell.original.push(sample.channels()[0].to_f64());
-      cell.buf.push(sample.channels()[0].to_f64());
+      let sample = sample.channels()[0].to_f64();
+      cell.original.push(sample);
       cell.capacity -= 1;
 
-      if cell.buf.len() == cell.freq.step_size() {
-         // feed audio and clear buffer
-         let buf = cell.buf.clone();
-         let wv = cell.freq.feed_audio(&buf);
-         cell.buf.clear();
-
+      for wv in cell.freq.feed(&[sample]) {
          cell.current_wavelet = Some(wv.clone());
-         let mut audio = vec![0.0; cell.resynth.step_size()];
-         cell.resynth.pull_audio(&mut audio, Some(wv));
-         cell.output.extend_from_slice(&audio);
+         cell.resynth.push_wavelet(wv);
       }
+
+      let mut audio = vec![0.0; cell.resynth.available()];
+      cell.resynth.pull(&mut audio);
+      cell.output.extend_from_slice(&audio);
    }
 
    fn capacity(&self) -> usize {
@@ -48,11 +44,10 @@ impl Sink<Mono64> for FreqSink {
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
    let sink = FreqSink(Rc::new(RefCell::new(FreqSinkInner {
-      buf: vec![],
       current_wavelet: None,
       capacity: (1 << 16),
-      freq: Frequencer::new(48000, 4096, 1024).unwrap(),
-      resynth: Resynth::new(48000, 4096, 1024).unwrap(),
+      freq: FrequencerStream::new(Frequencer::new(48000, 4096, 1024).unwrap()),
+      resynth: ResynthStream::new(Resynth::new(48000, 4096, 1024).unwrap()),
       original: vec![],
       output: vec![],
    })));
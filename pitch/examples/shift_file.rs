@@ -0,0 +1,27 @@
+//! Shift the pitch of a WAV file, e.g.
+//! `cargo run --example shift_file -- input.wav output.wav 1.5`
+
+use pitch::{effect::PitchShift, wav, MultiChannelProcessor};
+
+fn main() {
+   let args = std::env::args().collect::<Vec<_>>();
+   if args.len() != 4 {
+      eprintln!("usage: {} <input.wav> <output.wav> <ratio>", args[0]);
+      std::process::exit(1);
+   }
+   let ratio = args[3].parse::<f64>().expect("invalid ratio");
+
+   let spec = wav::WavReader::open(&args[1]).unwrap().spec();
+   let mut processor = MultiChannelProcessor::new(
+      spec.sample_rate,
+      4096,
+      1024,
+      spec.channels,
+      PitchShift::<f64>::new(ratio).expect("invalid ratio"),
+   )
+   .unwrap();
+   // Keep the stereo image when shifting
+   processor.set_coherent(true);
+
+   wav::process_file(&args[1], &args[2], &mut processor).unwrap();
+}
@@ -6,17 +6,16 @@ use fon::{
    Sample, Sink, Stream,
 };
 use pasts::prelude::*;
-use std::{cell::RefCell, collections::VecDeque};
+use std::cell::RefCell;
 
 use wavy::{Microphone, MicrophoneId, SpeakerId};
 
-use pitch::{Frequencer, Resynth, Wavelet};
+use pitch::{Frequencer, FrequencerStream, Resynth, ResynthStream};
 
 /// The program's shared state.
 struct State {
-   freq: Frequencer,
-   resynth: Resynth,
-   wavelets: VecDeque<Wavelet>,
+   freq: FrequencerStream,
+   resynth: ResynthStream,
 }
 
 /// Microphone task (record audio).
@@ -25,23 +24,18 @@ async fn microphone_task(state: &RefCell<State>, mut mic: Microphone<Ch16>) {
 
    loop {
       let mut sample = mic.record().await;
-      let step_size = state.borrow().freq.step_size();
 
       while let Some(stream) = sample.stream_sample() {
          let chan = stream.channels()[0];
          buffer.push(chan.to_f64());
+      }
 
-         // If there is enough data in the buffer we process it into a wavelet
-         if buffer.len() >= step_size {
-            let mut state = state.borrow_mut();
-            let mut wv = state.freq.feed_audio(&buffer[..]);
-
-            wv.pitch_shift(1.0 / 1.6);
-
-            state.wavelets.push_back(wv);
-            buffer.clear();
-         }
+      let mut state = state.borrow_mut();
+      for mut wv in state.freq.feed(&buffer) {
+         wv.pitch_shift(1.0 / 1.6);
+         state.resynth.push_wavelet(wv);
       }
+      buffer.clear();
    }
 }
 
@@ -54,18 +48,16 @@ async fn speakers_task(state: &RefCell<State>) {
       let mut sink = speakers.play().await;
       let mut state = state.borrow_mut();
 
-      // get the new wavelet if we have one
-      let wv = state.wavelets.pop_front();
-      println!("stored {} wavelets", state.wavelets.len());
+      println!("stored {} wavelets", state.resynth.queued());
 
       // allocate new output
-      let mut output = vec![0.0f64; state.resynth.step_size()];
+      let mut output = vec![0.0f64; state.resynth.resynth().step_size()];
 
-      // do the synthesis
-      state.resynth.pull_audio(&mut output, wv);
+      // do the synthesis, missing samples stay silent
+      state.resynth.pull(&mut output);
 
       for s in output.iter() {
-         sink.sink_sample(Mono16::new::<Ch16>((*s as f64).into()));
+         sink.sink_sample(Mono16::new::<Ch16>((*s).into()));
       }
    }
 }
@@ -80,9 +72,8 @@ async fn start() {
    );
 
    let state = RefCell::new(State {
-      freq: Frequencer::new(sample_rate as usize, 4096, 1024).unwrap(),
-      resynth: Resynth::new(sample_rate as usize, 4096, 1024).unwrap(),
-      wavelets: VecDeque::new(),
+      freq: FrequencerStream::new(Frequencer::new(sample_rate as usize, 4096, 1024).unwrap()),
+      resynth: ResynthStream::new(Resynth::new(sample_rate as usize, 4096, 1024).unwrap()),
    });
    // Create speaker and microphone tasks.
    task! {
@@ -0,0 +1,228 @@
+use crate::{
+   detect::{Hps, PitchEstimate},
+   effect::{FrameContext, SpectralEffect},
+   notes::{Key, Note, Scale},
+   Sample, ShiftMode, Tuning, Wavelet,
+};
+
+/// Automatic pitch correction, that pulls a voice onto the notes of a scale.
+///
+/// The pitch of every frame is detected, and the frame is shifted towards the
+/// nearest note of the scale.
+pub struct AutoTune {
+   root: Note,
+   scale: Scale,
+   tuning: Tuning,
+   retune_speed: f64,
+   tolerance: f64,
+   min_confidence: f64,
+   mode: ShiftMode,
+   detector: Hps,
+   correction: f64,
+   last_estimate: Option<PitchEstimate>,
+}
+
+impl AutoTune {
+   /// Create an auto tune to the scale starting at `root`, that snaps instantly.
+   pub fn new(root: Note, scale: Scale) -> Self {
+      Self {
+         root,
+         scale,
+         tuning: Tuning::default(),
+         retune_speed: 0.0,
+         tolerance: 0.0,
+         min_confidence: 0.5,
+         mode: ShiftMode::Interpolated,
+         detector: Hps::new(),
+         correction: 0.0,
+         last_estimate: None,
+      }
+   }
+
+   pub fn set_scale(&mut self, root: Note, scale: Scale) {
+      self.root = root;
+      self.scale = scale;
+   }
+
+   /// Pull the voice onto the notes of `key`.
+   pub fn set_key(&mut self, key: &Key) {
+      self.set_scale(key.root(4), key.scale().clone());
+   }
+
+   /// Set the tuning, whose notes the voice is pulled onto.
+   pub fn set_tuning(&mut self, tuning: Tuning) {
+      self.tuning = tuning;
+   }
+
+   pub fn tuning(&self) -> &Tuning {
+      &self.tuning
+   }
+
+   /// Set the time in seconds, in which the correction glides to the target note.
+   ///
+   /// A retune speed of `0.0` snaps hard to the target note.
+   pub fn set_retune_speed(&mut self, retune_speed: f64) {
+      self.retune_speed = retune_speed;
+   }
+
+   /// Set a window in cents around every note, inside of which the pitch is left alone.
+   ///
+   /// This keeps some of the natural variation of the voice.
+   pub fn set_tolerance(&mut self, tolerance: f64) {
+      self.tolerance = tolerance;
+   }
+
+   /// Set the confidence below which frames are considered unvoiced and left alone.
+   pub fn set_min_confidence(&mut self, min_confidence: f64) {
+      self.min_confidence = min_confidence;
+   }
+
+   pub fn set_mode(&mut self, mode: ShiftMode) {
+      self.mode = mode;
+   }
+
+   pub fn detector_mut(&mut self) -> &mut Hps {
+      &mut self.detector
+   }
+
+   /// The correction currently applied, in cents.
+   pub fn correction(&self) -> f64 {
+      self.correction
+   }
+
+   /// The pitch detected in the last frame.
+   pub fn last_estimate(&self) -> Option<PitchEstimate> {
+      self.last_estimate
+   }
+
+   /// Compute the correction in cents needed to move `frequency` onto the scale.
+   fn target_correction(&self, frequency: f64) -> f64 {
+      let note = self.tuning.frequency_to_approx_note(frequency);
+      let target = match self.scale.nearest(&self.root, note) {
+         Some(target) => target,
+         None => return 0.0,
+      };
+
+      let cents = 1200.0 * f64::log2(self.tuning.frequency(&target) / frequency);
+      if cents.abs() <= self.tolerance {
+         0.0
+      } else {
+         cents
+      }
+   }
+}
+
+impl<T: Sample> SpectralEffect<T> for AutoTune {
+   fn process(&mut self, wavelet: &mut Wavelet<T>, ctx: &FrameContext) {
+      self.last_estimate = self.detector.detect(wavelet, ctx);
+
+      let target = match self.last_estimate {
+         Some(estimate) if estimate.is_voiced(self.min_confidence) => {
+            self.target_correction(estimate.frequency)
+         }
+         _ => 0.0,
+      };
+
+      // Glide towards the target with the retune speed as time constant
+      let alpha = if self.retune_speed > 0.0 {
+         1.0 - f64::exp(-ctx.step_duration() / self.retune_speed)
+      } else {
+         1.0
+      };
+      self.correction += (target - self.correction) * alpha;
+
+      if self.correction != 0.0 {
+         wavelet.pitch_shift_with(2.0f64.powf(self.correction / 1200.0), self.mode);
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::{notes::note_to_frequency, Temperament};
+
+   fn voice(frequency: f64, ctx: &FrameContext) -> Wavelet {
+      // Harmonics leaking into the neighbouring bins, like after windowing
+      let mut wavelet = Wavelet::empty(ctx.frame_size / 2);
+      for (k, bin) in wavelet.bins.iter_mut().enumerate() {
+         for h in 1..6 {
+            let freq = h as f64 * frequency;
+            let distance = k as f64 - freq / ctx.freqs_per_bin();
+            let amplitude = f64::exp(-distance * distance) / h as f64;
+            if amplitude > bin.amplitude {
+               bin.amplitude = amplitude;
+               bin.frequency = freq;
+            }
+         }
+      }
+      wavelet
+   }
+
+   fn ctx() -> FrameContext {
+      FrameContext {
+         sample_rate: 48000,
+         frame_size: 8192,
+         step_size: 1024,
+         frame_index: 0,
+      }
+   }
+
+   #[test]
+   fn snaps_to_scale() {
+      let ctx = ctx();
+
+      // A major, sung slightly sharp A#
+      let mut tune = AutoTune::new("A4".parse().unwrap(), Scale::major());
+      let mut wavelet = voice(470.0, &ctx);
+      tune.process(&mut wavelet, &ctx);
+
+      // The nearest note in A major is B
+      let b = note_to_frequency(2.0);
+      let expected = 1200.0 * f64::log2(b / 470.0);
+      assert!((tune.correction() - expected).abs() < 1.0);
+   }
+
+   #[test]
+   fn tolerance_and_glide() {
+      let ctx = ctx();
+
+      let mut tune = AutoTune::new("A4".parse().unwrap(), Scale::chromatic());
+      tune.set_tolerance(20.0);
+
+      // 10 cents sharp stays untouched
+      tune.process(&mut voice(440.0 * 2.0f64.powf(10.0 / 1200.0), &ctx), &ctx);
+      assert_eq!(tune.correction(), 0.0);
+
+      // 40 cents sharp glides towards the note
+      tune.set_retune_speed(0.1);
+      let sharp = 440.0 * 2.0f64.powf(40.0 / 1200.0);
+      tune.process(&mut voice(sharp, &ctx), &ctx);
+      let first = tune.correction();
+      tune.process(&mut voice(sharp, &ctx), &ctx);
+      let second = tune.correction();
+      assert!(first < 0.0 && second < first && second > -40.0);
+   }
+
+   #[test]
+   fn follows_tuning() {
+      let ctx = ctx();
+
+      // A4 at 440Hz is 25 cents sharp of A4 at 433.69Hz
+      let reference = 440.0 * 2.0f64.powf(-25.0 / 1200.0);
+      let mut tune = AutoTune::new("A4".parse().unwrap(), Scale::chromatic());
+      tune.set_tuning(Tuning::new(reference).unwrap());
+      tune.process(&mut voice(440.0, &ctx), &ctx);
+      assert!((tune.correction() + 25.0).abs() < 1.0);
+
+      // With A4 at 440Hz, the just fifth above C is 18 cents sharp of the equal tempered G
+      let c = "C4".parse().unwrap();
+      let mut tune = AutoTune::new(c, Scale::major());
+      tune.set_tuning(Tuning::with_temperament(440.0, Temperament::JustIntonation, 0).unwrap());
+      let g = note_to_frequency(-2.0);
+      tune.process(&mut voice(g, &ctx), &ctx);
+      let expected = 1200.0 * f64::log2(tune.tuning().frequency(&"G4".parse().unwrap()) / g);
+      assert!((tune.correction() - expected).abs() < 1.0);
+      assert!(expected > 15.0);
+   }
+}
@@ -0,0 +1,385 @@
+//! Estimation of the fundamental frequency of audio.
+//!
+//! Unlike [`Wavelet::base_freq`], which returns the loudest bin, the detectors
+//! here look for the common fundamental of all harmonics.
+
+use alloc::vec::Vec;
+
+use crate::{cast, to_f64, Error, FrameContext, Sample, Wavelet};
+
+/// The result of a pitch detection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PitchEstimate {
+   /// The fundamental frequency in Hz.
+   pub frequency: f64,
+   /// How certain the detector is about the estimate, between `0.0` and `1.0`.
+   pub confidence: f64,
+}
+
+impl PitchEstimate {
+   /// Whether the audio should be considered voiced, i.e. has a pitch at all.
+   pub fn is_voiced(&self, threshold: f64) -> bool {
+      self.confidence >= threshold
+   }
+}
+
+/// The YIN pitch detector, working on time domain audio.
+///
+/// See de Cheveigné and Kawahara, "YIN, a fundamental frequency estimator for
+/// speech and music".
+pub struct Yin<T = f64> {
+   sample_rate: usize,
+   threshold: f64,
+   min_freq: f64,
+   max_freq: f64,
+   diff: Vec<T>,
+}
+
+impl<T: Sample> Yin<T> {
+   /// Create a detector for the typical range of voices and instruments.
+   pub fn new(sample_rate: usize) -> Self {
+      Self {
+         sample_rate,
+         threshold: 0.15,
+         min_freq: 50.0,
+         max_freq: 2000.0,
+         diff: vec![],
+      }
+   }
+
+   /// Create a detector, that only detects frequencies between `min_freq` and `max_freq`.
+   ///
+   /// Fails with [`Error::InvalidParameter`] if the frequencies are not finite
+   /// and positive, or `min_freq` is not below `max_freq`.
+   pub fn with_range(sample_rate: usize, min_freq: f64, max_freq: f64) -> Result<Self, Error> {
+      check_range(min_freq, max_freq)?;
+      Ok(Self {
+         min_freq,
+         max_freq,
+         ..Self::new(sample_rate)
+      })
+   }
+
+   /// The threshold on the normalized difference, below which a period is accepted.
+   pub fn set_threshold(&mut self, threshold: f64) {
+      self.threshold = threshold;
+   }
+
+   /// The number of samples needed to detect the lowest frequency.
+   pub fn min_len(&self) -> usize {
+      ((self.sample_rate as f64 / self.min_freq).ceil() as usize).saturating_mul(2)
+   }
+
+   /// Detect the pitch of `audio`.
+   ///
+   /// Half of the audio is used as the integration window, the other half to
+   /// look for periods.
+   /// Returns `None` if the audio is silent or too short to contain a period.
+   pub fn detect(&mut self, audio: &[T]) -> Option<PitchEstimate> {
+      let window = audio.len() / 2;
+      let min_tau = usize::max(2, (self.sample_rate as f64 / self.max_freq) as usize);
+      let max_tau = usize::min(window, (self.sample_rate as f64 / self.min_freq) as usize);
+      if min_tau.saturating_add(2) > max_tau {
+         return None;
+      }
+
+      // Squared difference function
+      self.diff.clear();
+      self.diff.extend((0..=max_tau).map(|tau| {
+         audio[..window]
+            .iter()
+            .zip(audio[tau..tau + window].iter())
+            .map(|(a, b)| (*a - *b) * (*a - *b))
+            .sum::<T>()
+      }));
+
+      // Cumulative mean normalized difference
+      let mut sum = T::zero();
+      self.diff[0] = T::one();
+      for tau in 1..=max_tau {
+         sum = sum + self.diff[tau];
+         self.diff[tau] = if sum > T::zero() {
+            self.diff[tau] * cast(tau as f64) / sum
+         } else {
+            T::one()
+         };
+      }
+      if sum.is_nan() || sum <= T::zero() {
+         return None;
+      }
+
+      // Take the first dip below the threshold, or the global minimum otherwise
+      let d = &self.diff;
+      let threshold = cast::<T>(self.threshold);
+      let tau = match (min_tau..max_tau).find(|tau| d[*tau] < threshold) {
+         Some(mut tau) => {
+            while tau + 1 < max_tau && d[tau + 1] < d[tau] {
+               tau += 1;
+            }
+            tau
+         }
+         None => (min_tau..max_tau)
+            .min_by(|a, b| d[*a].total_cmp(&d[*b]))
+            .unwrap(),
+      };
+
+      // Refine the period by parabolic interpolation
+      let (left, center, right) = (to_f64(d[tau - 1]), to_f64(d[tau]), to_f64(d[tau + 1]));
+      let denom = left - 2.0 * center + right;
+      let offset = if denom.abs() > f64::EPSILON {
+         0.5 * (left - right) / denom
+      } else {
+         0.0
+      };
+
+      Some(PitchEstimate {
+         frequency: self.sample_rate as f64 / (tau as f64 + offset),
+         confidence: (1.0 - center).clamp(0.0, 1.0),
+      })
+   }
+}
+
+/// The harmonic product spectrum pitch detector, working on [`Wavelet`]s.
+///
+/// The spectrum is downsampled by every harmonic number and multiplied,
+/// such that the harmonics of the fundamental add up in the fundamental's bin.
+pub struct Hps {
+   harmonics: usize,
+   min_freq: f64,
+   max_freq: f64,
+}
+
+impl Hps {
+   /// Create a detector for the typical range of voices and instruments.
+   pub fn new() -> Self {
+      Self {
+         harmonics: 4,
+         min_freq: 50.0,
+         max_freq: 2000.0,
+      }
+   }
+
+   /// Create a detector, that only detects frequencies between `min_freq` and `max_freq`.
+   ///
+   /// Fails with [`Error::InvalidParameter`] if the frequencies are not finite
+   /// and positive, or `min_freq` is not below `max_freq`.
+   pub fn with_range(min_freq: f64, max_freq: f64) -> Result<Self, Error> {
+      check_range(min_freq, max_freq)?;
+      Ok(Self {
+         min_freq,
+         max_freq,
+         ..Self::new()
+      })
+   }
+
+   /// Set the number of harmonics, that are multiplied.
+   pub fn set_harmonics(&mut self, harmonics: usize) {
+      self.harmonics = usize::max(harmonics, 1);
+   }
+
+   /// Detect the pitch of `wavelet`.
+   ///
+   /// The frequency is the one estimated by the phase vocoder, and thus more
+   /// precise than the bin resolution.
+   /// Returns `None` if the wavelet is silent.
+   pub fn detect<T: Sample>(
+      &self,
+      wavelet: &Wavelet<T>,
+      ctx: &FrameContext,
+   ) -> Option<PitchEstimate> {
+      let bins = &wavelet.bins;
+      let freqs_per_bin = ctx.freqs_per_bin();
+      let min_bin = usize::max(1, (self.min_freq / freqs_per_bin) as usize);
+      let max_bin = usize::min(
+         (self.max_freq / freqs_per_bin).ceil() as usize,
+         bins.len().saturating_sub(1) / self.harmonics,
+      );
+
+      let product = |k: usize| {
+         (1..=self.harmonics)
+            .map(|h| to_f64(bins[k * h].amplitude))
+            .product::<f64>()
+      };
+
+      // NaN amplitudes sort above everything, and are rejected below
+      let best = (min_bin..=max_bin).max_by(|a, b| product(*a).total_cmp(&product(*b)))?;
+      let peak = product(best);
+      if peak.is_nan() || peak <= 0.0 {
+         return None;
+      }
+
+      // The confidence is the share of the energy found in the harmonics
+      let energy = |k: usize| to_f64(bins[k].amplitude * bins[k].amplitude);
+      let total = bins
+         .iter()
+         .map(|bin| to_f64(bin.amplitude * bin.amplitude))
+         .sum::<f64>();
+      // The refined frequency must be usable to locate the harmonics
+      let frequency = to_f64(bins[best].frequency);
+      if frequency.is_nan() || frequency <= 0.0 {
+         return None;
+      }
+
+      let harmonic = (1..=bins.len() / best)
+         .map(|h| (h as f64 * frequency / freqs_per_bin).round() as usize)
+         .take_while(|k| *k < bins.len())
+         .flat_map(|k| (k.saturating_sub(1)..=k + 1).filter(|k| *k < bins.len()))
+         .map(energy)
+         .sum::<f64>();
+
+      Some(PitchEstimate {
+         frequency,
+         confidence: (harmonic / total).clamp(0.0, 1.0),
+      })
+   }
+}
+
+impl Default for Hps {
+   fn default() -> Self {
+      Self::new()
+   }
+}
+
+fn check_range(min_freq: f64, max_freq: f64) -> Result<(), Error> {
+   if !min_freq.is_finite() || !max_freq.is_finite() || min_freq <= 0.0 || min_freq >= max_freq {
+      return Err(Error::InvalidParameter("frequency range"));
+   }
+   Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::FrequencyBin;
+   use core::f64::consts::PI;
+
+   #[test]
+   fn yin_finds_fundamental() {
+      // The second harmonic is the loudest
+      let audio = (0..4096)
+         .map(|k| {
+            let t = 2.0 * PI * 220.0 * k as f64 / 48000.0;
+            0.3 * f64::sin(t) + 1.0 * f64::sin(2.0 * t) + 0.5 * f64::sin(3.0 * t)
+         })
+         .collect::<Vec<_>>();
+
+      let estimate = Yin::new(48000).detect(&audio).unwrap();
+      assert!((estimate.frequency - 220.0).abs() < 0.5, "{:?}", estimate);
+      assert!(estimate.is_voiced(0.9));
+   }
+
+   #[test]
+   fn yin_in_f32() {
+      let audio = (0..4096)
+         .map(|k| f32::sin(2.0 * core::f32::consts::PI * 220.0 * k as f32 / 48000.0))
+         .collect::<Vec<_>>();
+
+      let estimate = Yin::new(48000).detect(&audio).unwrap();
+      assert!((estimate.frequency - 220.0).abs() < 0.5, "{:?}", estimate);
+   }
+
+   #[test]
+   fn yin_silence() {
+      assert_eq!(Yin::new(48000).detect(&[0.0; 4096]), None);
+   }
+
+   #[test]
+   fn nan_input() {
+      let mut audio = [0.0; 4096];
+      audio[100] = f64::NAN;
+      assert_eq!(Yin::new(48000).detect(&audio), None);
+
+      let ctx = FrameContext {
+         sample_rate: 48000,
+         frame_size: 4096,
+         step_size: 1024,
+         frame_index: 0,
+      };
+      let mut wavelet = Wavelet::empty(2048);
+      wavelet.bins[20].amplitude = 1.0;
+      wavelet.bins[40].amplitude = f64::NAN;
+      assert_eq!(Hps::new().detect(&wavelet, &ctx), None);
+   }
+
+   #[test]
+   fn hps_finds_fundamental() {
+      let ctx = FrameContext {
+         sample_rate: 48000,
+         frame_size: 4096,
+         step_size: 1024,
+         frame_index: 0,
+      };
+
+      // Harmonics of 20 bins, where the second harmonic is the loudest
+      let mut wavelet = Wavelet::empty(2048);
+      for (h, amplitude) in [0.3, 1.0, 0.5, 0.4, 0.2].iter().enumerate() {
+         let k = 20 * (h + 1);
+         wavelet.bins[k] = FrequencyBin {
+            amplitude: *amplitude,
+            frequency: k as f64 * ctx.freqs_per_bin() + 1.0,
+            phase: 0.0,
+         };
+         wavelet.bins[k - 1].amplitude = 0.1 * amplitude;
+         wavelet.bins[k + 1].amplitude = 0.1 * amplitude;
+      }
+
+      assert_eq!(wavelet.base_freq(), 40.0 * ctx.freqs_per_bin() + 1.0);
+
+      let estimate = Hps::new().detect(&wavelet, &ctx).unwrap();
+      assert_eq!(estimate.frequency, 20.0 * ctx.freqs_per_bin() + 1.0);
+      assert!(estimate.is_voiced(0.9));
+   }
+
+   #[test]
+   fn hps_invalid_bin_frequency() {
+      let ctx = FrameContext {
+         sample_rate: 48000,
+         frame_size: 4096,
+         step_size: 1024,
+         frame_index: 0,
+      };
+
+      for &frequency in [0.0, -100.0, f64::NAN, 1e-300].iter() {
+         let mut wavelet = Wavelet::empty(2048);
+         for h in 1..=5 {
+            wavelet.bins[20 * h].amplitude = 1.0;
+            wavelet.bins[20 * h].frequency = 20.0 * h as f64 * ctx.freqs_per_bin();
+         }
+         wavelet.bins[20].frequency = frequency;
+
+         let estimate = Hps::new().detect(&wavelet, &ctx);
+         if frequency > 0.0 {
+            assert_eq!(estimate.unwrap().frequency, frequency);
+         } else {
+            assert_eq!(estimate, None);
+         }
+      }
+   }
+
+   #[test]
+   fn invalid_range() {
+      for (min, max) in [
+         (50.0, 0.0),
+         (0.0, 2000.0),
+         (-50.0, 2000.0),
+         (2000.0, 50.0),
+         (50.0, 50.0),
+         (f64::NAN, 2000.0),
+         (50.0, f64::INFINITY),
+      ] {
+         assert!(matches!(
+            Yin::<f64>::with_range(48000, min, max),
+            Err(Error::InvalidParameter("frequency range"))
+         ));
+         assert!(matches!(
+            Hps::with_range(min, max),
+            Err(Error::InvalidParameter("frequency range"))
+         ));
+      }
+
+      // Extreme but valid ranges never find a period
+      let mut yin = Yin::with_range(48000, 1e-300, 1e-299).unwrap();
+      assert_eq!(yin.min_len(), usize::MAX);
+      assert_eq!(yin.detect(&[0.5; 4096]), None);
+   }
+}
@@ -0,0 +1,302 @@
+//! Spectral effects, that modify [`Wavelet`]s between analysis and resynthesis.
+
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::{
+   envelope::{sample_envelope, SpectralEnvelope},
+   Error, Sample, ShiftMode, Wavelet,
+};
+
+/// Information about the frame an effect is applied to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameContext {
+   pub sample_rate: usize,
+   pub frame_size: usize,
+   pub step_size: usize,
+   /// The number of frames processed before this one.
+   pub frame_index: u64,
+}
+
+impl FrameContext {
+   /// The frequency distance between two bins.
+   pub fn freqs_per_bin(&self) -> f64 {
+      self.sample_rate as f64 / self.frame_size as f64
+   }
+
+   /// The duration of a step in seconds.
+   pub fn step_duration(&self) -> f64 {
+      self.step_size as f64 / self.sample_rate as f64
+   }
+}
+
+/// An algorithm that modifies the spectrum of the audio frame by frame.
+pub trait SpectralEffect<T: Sample = f64> {
+   fn process(&mut self, wavelet: &mut Wavelet<T>, ctx: &FrameContext);
+}
+
+impl<T: Sample, F: FnMut(&mut Wavelet<T>, &FrameContext)> SpectralEffect<T> for F {
+   fn process(&mut self, wavelet: &mut Wavelet<T>, ctx: &FrameContext) {
+      self(wavelet, ctx)
+   }
+}
+
+/// Shifts the pitch by a constant ratio, using [`Wavelet::pitch_shift_with`].
+///
+/// By default, the formants move together with the pitch.
+/// With a formant ratio set, the spectral envelope is estimated, removed before
+/// shifting and reapplied afterwards, scaled by the formant ratio.
+/// A formant ratio of `1.0` preserves the formants of the original voice.
+#[derive(Clone)]
+pub struct PitchShift<T = f64> {
+   ratio: f64,
+   mode: ShiftMode,
+   formant_ratio: Option<f64>,
+   estimator: SpectralEnvelope<T>,
+   envelope: Vec<T>,
+}
+
+impl<T: Sample> PitchShift<T> {
+   /// Create a pitch shift by `ratio`, where `2.0` shifts up by an octave.
+   ///
+   /// Fails with [`Error::InvalidParameter`] if the ratio is not finite and positive.
+   pub fn new(ratio: f64) -> Result<Self, Error> {
+      let mut shift = Self {
+         ratio: 1.0,
+         mode: ShiftMode::Fast,
+         formant_ratio: None,
+         // A cutoff of 32 coefficients stays below the period of typical voices
+         estimator: SpectralEnvelope::new(32),
+         envelope: vec![],
+      };
+      shift.set_ratio(ratio)?;
+      Ok(shift)
+   }
+
+   /// Create a pitch shift, that shifts the formants independently by `formant_ratio`.
+   pub fn with_formants(ratio: f64, formant_ratio: f64) -> Result<Self, Error> {
+      let mut shift = Self::new(ratio)?;
+      shift.set_formant_ratio(Some(formant_ratio))?;
+      Ok(shift)
+   }
+
+   pub fn ratio(&self) -> f64 {
+      self.ratio
+   }
+
+   /// Fails with [`Error::InvalidParameter`] if the ratio is not finite and positive.
+   pub fn set_ratio(&mut self, ratio: f64) -> Result<(), Error> {
+      if !ratio.is_finite() || ratio <= 0.0 {
+         return Err(Error::InvalidParameter("pitch ratio"));
+      }
+      self.ratio = ratio;
+      Ok(())
+   }
+
+   pub fn mode(&self) -> ShiftMode {
+      self.mode
+   }
+
+   pub fn set_mode(&mut self, mode: ShiftMode) {
+      self.mode = mode;
+   }
+
+   pub fn formant_ratio(&self) -> Option<f64> {
+      self.formant_ratio
+   }
+
+   /// Set the formant ratio, or `None` to let the formants follow the pitch.
+   ///
+   /// Fails with [`Error::InvalidParameter`] if the ratio is not finite and positive.
+   pub fn set_formant_ratio(&mut self, formant_ratio: Option<f64>) -> Result<(), Error> {
+      if let Some(ratio) = formant_ratio {
+         if !ratio.is_finite() || ratio <= 0.0 {
+            return Err(Error::InvalidParameter("formant ratio"));
+         }
+      }
+
+      self.formant_ratio = formant_ratio;
+      Ok(())
+   }
+
+   /// Access the envelope estimator, e.g. to tune its lifter.
+   pub fn estimator_mut(&mut self) -> &mut SpectralEnvelope<T> {
+      &mut self.estimator
+   }
+}
+
+impl<T: Sample> SpectralEffect<T> for PitchShift<T> {
+   fn process(&mut self, wavelet: &mut Wavelet<T>, _ctx: &FrameContext) {
+      let formant_ratio = match self.formant_ratio {
+         Some(formant_ratio) => formant_ratio,
+         None => {
+            wavelet.pitch_shift_with(self.ratio, self.mode);
+            return;
+         }
+      };
+
+      // Flatten the spectrum, such that only the excitation remains
+      self.estimator.estimate(wavelet, &mut self.envelope);
+      wavelet
+         .bins
+         .iter_mut()
+         .zip(self.envelope.iter())
+         .for_each(|(bin, env)| bin.amplitude = bin.amplitude / *env);
+
+      wavelet.pitch_shift_with(self.ratio, self.mode);
+
+      // Reapply the envelope, shifted by the formant ratio
+      let envelope = &self.envelope;
+      wavelet.bins.iter_mut().enumerate().for_each(|(k, bin)| {
+         bin.amplitude = bin.amplitude * sample_envelope(envelope, k as f64 / formant_ratio)
+      });
+   }
+}
+
+/// Runs a number of effects one after another.
+#[derive(Default)]
+pub struct Chain<T: Sample = f64> {
+   effects: Vec<Box<dyn SpectralEffect<T>>>,
+}
+
+impl<T: Sample> Chain<T> {
+   pub fn new() -> Self {
+      Self::default()
+   }
+
+   /// Append an effect to the end of the chain.
+   pub fn push<E: SpectralEffect<T> + 'static>(&mut self, effect: E) {
+      self.effects.push(Box::new(effect));
+   }
+
+   /// Like [`Chain::push`] but in builder style.
+   pub fn with<E: SpectralEffect<T> + 'static>(mut self, effect: E) -> Self {
+      self.push(effect);
+      self
+   }
+
+   pub fn len(&self) -> usize {
+      self.effects.len()
+   }
+
+   pub fn is_empty(&self) -> bool {
+      self.effects.is_empty()
+   }
+}
+
+impl<T: Sample> SpectralEffect<T> for Chain<T> {
+   fn process(&mut self, wavelet: &mut Wavelet<T>, ctx: &FrameContext) {
+      for effect in self.effects.iter_mut() {
+         effect.process(wavelet, ctx);
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::FrequencyBin;
+
+   #[test]
+   fn chain_runs_in_order() {
+      let ctx = FrameContext {
+         sample_rate: 48000,
+         frame_size: 8,
+         step_size: 2,
+         frame_index: 0,
+      };
+
+      let mut wavelet = Wavelet::empty(4);
+      wavelet.bins[1] = FrequencyBin {
+         amplitude: 1.0,
+         frequency: 6000.0,
+         phase: 0.0,
+      };
+
+      let mut chain = Chain::new()
+         .with(PitchShift::new(2.0).unwrap())
+         .with(|wv: &mut Wavelet, _: &FrameContext| wv.bins[2].amplitude *= 0.5);
+      assert_eq!(chain.len(), 2);
+
+      chain.process(&mut wavelet, &ctx);
+      assert_eq!(wavelet.bins[1].amplitude, 0.0);
+      assert_eq!(wavelet.bins[2].amplitude, 0.5);
+      assert_eq!(wavelet.bins[2].frequency, 12000.0);
+   }
+
+   #[test]
+   fn formants_stay_in_place() {
+      let ctx = FrameContext {
+         sample_rate: 48000,
+         frame_size: 2048,
+         step_size: 512,
+         frame_index: 0,
+      };
+
+      // Harmonics every 16 bins, with a formant around bin 200
+      let mut wavelet = Wavelet::empty(1024);
+      for (k, bin) in wavelet.bins.iter_mut().enumerate() {
+         let env = f64::exp(-((k as f64 - 200.0) / 60.0).powi(2));
+         bin.amplitude = if k % 16 == 0 { env } else { env * 1e-3 };
+         bin.frequency = k as f64 * ctx.freqs_per_bin();
+      }
+
+      let loudest = |wv: &Wavelet| {
+         (0..wv.bins.len())
+            .max_by(|a, b| {
+               wv.bins[*a]
+                  .amplitude
+                  .partial_cmp(&wv.bins[*b].amplitude)
+                  .unwrap()
+            })
+            .unwrap()
+      };
+
+      // Without correction, the formant moves up with the pitch
+      let mut shifted = wavelet.clone();
+      PitchShift::new(1.5).unwrap().process(&mut shifted, &ctx);
+      assert!(loudest(&shifted) > 280);
+
+      // With correction, it stays in place
+      let mut preserved = wavelet.clone();
+      PitchShift::with_formants(1.5, 1.0)
+         .unwrap()
+         .process(&mut preserved, &ctx);
+      let peak = loudest(&preserved) as i64;
+      assert!((peak - 200).abs() <= 16, "peak was {}", peak);
+
+      // And it can be shifted on its own
+      let mut formants = wavelet;
+      PitchShift::with_formants(1.0, 1.25)
+         .unwrap()
+         .process(&mut formants, &ctx);
+      let peak = loudest(&formants) as i64;
+      assert!((peak - 250).abs() <= 16, "peak was {}", peak);
+   }
+
+   #[test]
+   fn invalid_formant_ratio() {
+      let mut shift = PitchShift::<f64>::new(1.0).unwrap();
+      for ratio in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+         assert_eq!(
+            shift.set_formant_ratio(Some(ratio)),
+            Err(Error::InvalidParameter("formant ratio"))
+         );
+      }
+      assert_eq!(shift.formant_ratio(), None);
+      assert!(PitchShift::<f64>::with_formants(1.0, 0.0).is_err());
+   }
+
+   #[test]
+   fn invalid_ratio() {
+      let mut shift = PitchShift::<f64>::new(1.5).unwrap();
+      for ratio in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+         assert_eq!(
+            shift.set_ratio(ratio),
+            Err(Error::InvalidParameter("pitch ratio"))
+         );
+         assert!(PitchShift::<f64>::new(ratio).is_err());
+         assert!(PitchShift::<f64>::with_formants(ratio, 1.0).is_err());
+      }
+      assert_eq!(shift.ratio(), 1.5);
+   }
+}
@@ -0,0 +1,153 @@
+//! Estimation of the spectral envelope, i.e. the formants, of a [`Wavelet`].
+
+use alloc::vec::Vec;
+use num_complex::Complex;
+use num_traits::Zero;
+
+use crate::{cast, fft::ComplexFft, FrequencyBin, Sample, Wavelet};
+
+/// Amplitudes below this are treated as silence.
+const FLOOR: f64 = 1e-9;
+
+/// Estimates the spectral envelope of wavelets via cepstral liftering.
+///
+/// The log amplitude spectrum is transformed into the cepstrum, where all but
+/// the lowest `lifter` coefficients are discarded.
+/// This removes the fine harmonic structure and leaves the smooth envelope.
+#[derive(Clone)]
+pub struct SpectralEnvelope<T = f64> {
+   lifter: usize,
+   size: usize,
+   fft: Option<ComplexFft<T>>,
+   ifft: Option<ComplexFft<T>>,
+   buf: Vec<Complex<T>>,
+   cepstrum: Vec<Complex<T>>,
+}
+
+impl<T: Sample> SpectralEnvelope<T> {
+   /// Create an estimator keeping `lifter` cepstral coefficients.
+   ///
+   /// The lifter needs to stay below the period of the lowest pitch in samples,
+   /// otherwise the harmonics leak into the envelope.
+   pub fn new(lifter: usize) -> Self {
+      Self {
+         lifter,
+         size: 0,
+         fft: None,
+         ifft: None,
+         buf: vec![],
+         cepstrum: vec![],
+      }
+   }
+
+   /// Create an estimator with a lifter of `cutoff` seconds.
+   pub fn with_cutoff(sample_rate: usize, cutoff: f64) -> Self {
+      Self::new((cutoff * sample_rate as f64) as usize)
+   }
+
+   pub fn lifter(&self) -> usize {
+      self.lifter
+   }
+
+   pub fn set_lifter(&mut self, lifter: usize) {
+      self.lifter = lifter;
+   }
+
+   /// Estimate the envelope of `wavelet`, writing one value per bin into `envelope`.
+   pub fn estimate(&mut self, wavelet: &Wavelet<T>, envelope: &mut Vec<T>) {
+      let bins = wavelet.bins.len();
+      let size = 2 * bins;
+      envelope.clear();
+      if bins == 0 {
+         return;
+      }
+
+      if self.size != size {
+         self.size = size;
+         self.fft = ComplexFft::new(size, false).ok();
+         self.ifft = ComplexFft::new(size, true).ok();
+      }
+      let (fft, ifft) = match (&mut self.fft, &mut self.ifft) {
+         (Some(fft), Some(ifft)) => (fft, ifft),
+         // Without a transform of this size, fall back to the raw amplitudes
+         _ => {
+            envelope.extend(wavelet.bins.iter().map(|bin| bin.amplitude));
+            return;
+         }
+      };
+
+      // Build the symmetric log spectrum
+      let floor = cast::<T>(FLOOR);
+      let log_amp = |bin: &FrequencyBin<T>| Complex::new(bin.amplitude.max(floor).ln(), T::zero());
+      self.buf.clear();
+      self.buf.extend(wavelet.bins.iter().map(log_amp));
+      self.buf.push(log_amp(&wavelet.bins[bins - 1]));
+      self.buf.extend(wavelet.bins[1..].iter().rev().map(log_amp));
+
+      // Transform into the cepstrum and lifter
+      self.cepstrum.clear();
+      self.cepstrum.resize(size, Complex::zero());
+      ifft.process(&mut self.buf, &mut self.cepstrum);
+
+      let lifter = usize::min(self.lifter.max(1), bins);
+      for (k, c) in self.cepstrum.iter_mut().enumerate() {
+         if k >= lifter && k <= size - lifter {
+            *c = Complex::zero();
+         }
+      }
+
+      // Transform back into the smoothed log spectrum
+      fft.process(&mut self.cepstrum, &mut self.buf);
+      let scale = cast::<T>(size as f64);
+      envelope.extend(self.buf[..bins].iter().map(|x| (x.re / scale).exp()));
+   }
+}
+
+/// Sample `envelope` at the fractional bin `k` by linear interpolation.
+pub(crate) fn sample_envelope<T: Sample>(envelope: &[T], k: f64) -> T {
+   if k <= 0.0 {
+      return envelope.first().copied().unwrap_or_else(T::zero);
+   }
+
+   let index = k as usize;
+   if index + 1 >= envelope.len() {
+      return envelope.last().copied().unwrap_or_else(T::zero);
+   }
+
+   let frac = cast::<T>(k - index as f64);
+   envelope[index] * (T::one() - frac) + envelope[index + 1] * frac
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn envelope_of_harmonics() {
+      // Harmonics every 8 bins, under a falling envelope
+      let mut wavelet = Wavelet::empty(512);
+      for (k, bin) in wavelet.bins.iter_mut().enumerate() {
+         let env = f64::exp(-(k as f64) / 100.0);
+         bin.amplitude = if k % 8 == 0 { env } else { env * 1e-3 };
+      }
+
+      let mut estimator = SpectralEnvelope::new(20);
+      let mut envelope = vec![];
+      estimator.estimate(&wavelet, &mut envelope);
+      assert_eq!(envelope.len(), 512);
+
+      // The envelope falls and has no harmonic ripple
+      assert!(envelope[50] > envelope[250]);
+      assert!(envelope[250] > envelope[450]);
+      let ripple = envelope[200] / envelope[204];
+      assert!(ripple > 0.5 && ripple < 2.0, "ripple was {}", ripple);
+   }
+
+   #[test]
+   fn sampling_clamps_to_the_ends() {
+      let envelope = [1.0, 2.0, 3.0];
+      assert_eq!(sample_envelope(&envelope, -0.5), 1.0);
+      assert_eq!(sample_envelope(&envelope, 0.5), 1.5);
+      assert_eq!(sample_envelope(&envelope, 5.0), 3.0);
+   }
+}
@@ -0,0 +1,94 @@
+use core::fmt;
+
+/// The errors that can occur in the pitch crate.
+///
+/// More variants may be added, e.g. by enabling features like `std`.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Error {
+   /// The frame size is not supported, e.g. because it is odd.
+   InvalidFrameSize(usize),
+   /// The step size is zero or not smaller than the frame size.
+   InvalidStepSize { step_size: usize, frame_size: usize },
+   /// A buffer passed in has the wrong length.
+   BufferLength { expected: usize, actual: usize },
+   /// A wavelet has a different number of bins than expected.
+   WaveletSize { expected: usize, actual: usize },
+   /// Two parts of the processing chain run at different sample rates.
+   SampleRateMismatch { expected: usize, actual: usize },
+   /// A parameter is out of its valid range, e.g. a ratio that is not positive.
+   InvalidParameter(&'static str),
+   /// The number of audio channels is not supported.
+   ChannelCount { expected: usize, actual: usize },
+   /// A string could not be parsed as a note, or a note is out of range.
+   InvalidNote,
+   /// A Scala scale file could not be parsed.
+   InvalidScala,
+   /// A WAV file could not be parsed or uses an unsupported sample format.
+   InvalidWav,
+   /// Reading or writing failed.
+   #[cfg(feature = "std")]
+   Io(std::io::ErrorKind),
+}
+
+impl fmt::Display for Error {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      match self {
+         Error::InvalidFrameSize(size) => write!(f, "invalid frame size {}", size),
+         Error::InvalidStepSize {
+            step_size,
+            frame_size,
+         } => write!(
+            f,
+            "invalid step size {} for frame size {}",
+            step_size, frame_size
+         ),
+         Error::BufferLength { expected, actual } => {
+            write!(f, "buffer has length {}, expected {}", actual, expected)
+         }
+         Error::WaveletSize { expected, actual } => {
+            write!(f, "wavelet has {} bins, expected {}", actual, expected)
+         }
+         Error::SampleRateMismatch { expected, actual } => write!(
+            f,
+            "sample rate {} does not match expected sample rate {}",
+            actual, expected
+         ),
+         Error::ChannelCount { expected, actual } => {
+            write!(f, "{} channels given, expected {}", actual, expected)
+         }
+         Error::InvalidParameter(name) => write!(f, "invalid value for {}", name),
+         Error::InvalidNote => write!(f, "invalid note name"),
+         Error::InvalidScala => write!(f, "invalid scala file"),
+         Error::InvalidWav => write!(f, "invalid or unsupported wav file"),
+         #[cfg(feature = "std")]
+         Error::Io(kind) => write!(f, "i/o error: {}", kind),
+      }
+   }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for Error {
+   fn from(err: std::io::Error) -> Self {
+      Error::Io(err.kind())
+   }
+}
+
+/// Checks that `frame_size` and `step_size` describe a valid overlapping frame.
+pub(crate) fn check_frame(frame_size: usize, step_size: usize) -> Result<(), Error> {
+   if frame_size < 2 || frame_size % 2 != 0 {
+      return Err(Error::InvalidFrameSize(frame_size));
+   }
+
+   if step_size == 0 || step_size >= frame_size {
+      return Err(Error::InvalidStepSize {
+         step_size,
+         frame_size,
+      });
+   }
+
+   Ok(())
+}
@@ -1,93 +1,624 @@
-use alloc::vec::Vec;
-use core::f32::consts::PI;
-use num_complex::Complex32;
+//! Fast Fourier transforms, that do not need an external FFT library.
+//!
+//! [`FftPlan`] is an in-place FFT of any size over `f32` or `f64`, with the
+//! twiddle factors and the input reordering precomputed once per size.
+//! [`RealFft`] and [`RealIfft`] transform real signals with a complex FFT of half
+//! the length, which is done by `rustfft` if the `rustfft` feature is enabled,
+//! and by [`FftPlan`] otherwise.
 
-use crate::splat::SplatAccessor;
+#[cfg(feature = "alloc")]
+use alloc::{boxed::Box, vec::Vec};
+use core::{fmt::Debug, ops::Neg};
+use num_complex::Complex;
+use num_traits::Num;
+#[cfg(feature = "alloc")]
+use num_traits::Zero;
 
-pub fn fft<B: AsRef<[Complex32]>>(input: B) -> Result<Vec<Complex32>, ()> {
-   fft_inner(input, false)
+#[cfg(feature = "alloc")]
+use crate::Error;
+use crate::{cast, Sample};
+
+/// A floating point type that [`FftPlan`] can work with.
+pub trait FftFloat: Copy + Debug + Num + Neg<Output = Self> + 'static {
+   /// Convert from `f64`, rounding if necessary.
+   fn from_f64(x: f64) -> Self;
 }
 
-pub fn rfft<B: AsRef<[Complex32]>>(input: B) -> Result<Vec<Complex32>, ()> {
-   fft_inner(input, true)
+impl FftFloat for f32 {
+   fn from_f64(x: f64) -> Self {
+      x as f32
+   }
 }
 
-fn fft_inner<B: AsRef<[Complex32]>>(input: B, is_reverse: bool) -> Result<Vec<Complex32>, ()> {
-   let in_ref = input.as_ref();
-   if !in_ref.len().is_power_of_two() {
-      return Err(());
+impl FftFloat for f64 {
+   fn from_f64(x: f64) -> Self {
+      x
    }
+}
+
+/// A reusable plan for complex FFTs of a fixed size.
+///
+/// Sizes with no other prime factors than 2, 3 and 5 use a mixed-radix FFT,
+/// all other sizes use Bluestein's algorithm on top of a power of two FFT.
+/// The transforms work in place and do not allocate.
+/// Neither direction is normalized, so a forward followed by an inverse
+/// transform scales the input by `len`.
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+pub struct FftPlan<T> {
+   len: usize,
+   algorithm: Algorithm<T>,
+}
+
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone)]
+enum Algorithm<T> {
+   MixedRadix {
+      /// The radices of the butterfly passes, in the order they are applied.
+      factors: Vec<usize>,
+      /// The swaps bringing the input into digit reversed order.
+      swaps: Vec<(usize, usize)>,
+      /// `e^(-2πik / len)` for `k` from `0` to `len`.
+      twiddles: Vec<Complex<T>>,
+   },
+   Bluestein {
+      fft: Box<FftPlan<T>>,
+      /// `e^(-πik² / len)` for `k` from `0` to `len`.
+      chirp: Vec<Complex<T>>,
+      /// The transformed conjugate chirp, already normalized for the inner FFT.
+      kernel: Vec<Complex<T>>,
+      scratch: Vec<Complex<T>>,
+   },
+}
+
+#[cfg(feature = "alloc")]
+impl<T: FftFloat> FftPlan<T> {
+   /// Plan a transform of `len` values, which must not be zero.
+   pub fn new(len: usize) -> Result<Self, Error> {
+      if len == 0 {
+         return Err(Error::InvalidFrameSize(len));
+      }
+
+      let algorithm = match radix_factors(len) {
+         Some(factors) => Algorithm::MixedRadix {
+            swaps: digit_reversal(&factors),
+            factors,
+            twiddles: (0..len)
+               .map(|k| unit(-2.0 * k as f64 / len as f64))
+               .collect(),
+         },
+         None => {
+            let inner = (2 * len - 1).next_power_of_two();
+            let mut fft = FftPlan::new(inner)?;
 
-   let mut output = vec![Complex32::new(0.0, 0.0); in_ref.len()];
+            // k² modulo 2 len keeps the angles accurate for long transforms
+            let chirp = (0..len as u64)
+               .map(|k| unit(-(((k * k) % (2 * len as u64)) as f64) / len as f64))
+               .collect::<Vec<Complex<T>>>();
 
-   // Calculate omega
-   let omega = calc_omega(in_ref.len(), is_reverse);
+            let zero = Complex::new(T::zero(), T::zero());
+            let scale = T::from_f64(1.0 / inner as f64);
+            let mut kernel = vec![zero; inner];
+            kernel[0] = chirp[0].conj() * scale;
+            for (k, w) in chirp.iter().enumerate().skip(1) {
+               kernel[k] = w.conj() * scale;
+               kernel[inner - k] = w.conj() * scale;
+            }
+            fft.forward(&mut kernel)?;
 
-   // Start the actual fft
-   do_fft(&SplatAccessor::new(&input), &mut output, &omega, is_reverse);
+            Algorithm::Bluestein {
+               fft: Box::new(fft),
+               chirp,
+               kernel,
+               scratch: vec![zero; inner],
+            }
+         }
+      };
+
+      Ok(Self { len, algorithm })
+   }
+
+   pub fn len(&self) -> usize {
+      self.len
+   }
+
+   /// Never `true`, as transforms have at least one value.
+   pub fn is_empty(&self) -> bool {
+      self.len == 0
+   }
+
+   /// Transform `data` in place with the kernel `e^(-2πikn / len)`.
+   pub fn forward(&mut self, data: &mut [Complex<T>]) -> Result<(), Error> {
+      self.process(data, false)
+   }
 
-   if is_reverse {
-      let n = Complex32::new(output.len() as f32, 0.0);
-      for i in 0..output.len() {
-         output[i] = output[i] / n;
+   /// Transform `data` in place with the kernel `e^(2πikn / len)`.
+   pub fn inverse(&mut self, data: &mut [Complex<T>]) -> Result<(), Error> {
+      self.process(data, true)
+   }
+
+   fn process(&mut self, data: &mut [Complex<T>], inverse: bool) -> Result<(), Error> {
+      check_len(data.len(), self.len)?;
+
+      match &mut self.algorithm {
+         Algorithm::MixedRadix {
+            factors,
+            swaps,
+            twiddles,
+         } => {
+            for &(a, b) in swaps.iter() {
+               data.swap(a, b);
+            }
+
+            // Combine `radix` transforms of size `m` into one of size `m * radix`
+            let mut m = 1;
+            for &radix in factors.iter() {
+               let size = m * radix;
+               for block in data.chunks_exact_mut(size) {
+                  if radix == 2 {
+                     butterfly2(block, twiddles, twiddles.len() / size, inverse);
+                  } else {
+                     butterfly(block, radix, twiddles, inverse);
+                  }
+               }
+               m = size;
+            }
+         }
+         Algorithm::Bluestein {
+            fft,
+            chirp,
+            kernel,
+            scratch,
+         } => {
+            // The inverse is the conjugate of the forward transform of the conjugate
+            let conj = |x: Complex<T>| if inverse { x.conj() } else { x };
+
+            let zero = Complex::new(T::zero(), T::zero());
+            scratch.iter_mut().for_each(|x| *x = zero);
+            for ((y, x), w) in scratch.iter_mut().zip(data.iter()).zip(chirp.iter()) {
+               *y = conj(*x) * w;
+            }
+
+            // Convolve with the conjugate chirp
+            fft.forward(scratch)?;
+            for (y, k) in scratch.iter_mut().zip(kernel.iter()) {
+               *y = *y * k;
+            }
+            fft.inverse(scratch)?;
+
+            for ((x, y), w) in data.iter_mut().zip(scratch.iter()).zip(chirp.iter()) {
+               *x = conj(y * w);
+            }
+         }
+      }
+
+      Ok(())
+   }
+}
+
+/// `e^(iπx)`, calculated in double precision.
+pub(crate) fn unit<T: FftFloat>(x: f64) -> Complex<T> {
+   let angle = core::f64::consts::PI * x;
+   Complex::new(T::from_f64(angle.cos()), T::from_f64(angle.sin()))
+}
+
+/// The prime factors of `len`, if they are all 2, 3 or 5.
+#[cfg(feature = "alloc")]
+fn radix_factors(mut len: usize) -> Option<Vec<usize>> {
+   let mut factors = vec![];
+   for &radix in &[2, 3, 5] {
+      while len % radix == 0 {
+         factors.push(radix);
+         len /= radix;
       }
    }
 
-   Ok(output)
+   if len == 1 {
+      Some(factors)
+   } else {
+      None
+   }
 }
 
-fn do_fft(
-   input: &SplatAccessor<Complex32>,
-   output: &mut [Complex32],
-   omega: &Complex32,
-   is_reverse: bool,
+/// The swaps that reorder the input such that every pass of butterflies
+/// combines adjacent transforms.
+#[cfg(feature = "alloc")]
+fn digit_reversal(factors: &[usize]) -> Vec<(usize, usize)> {
+   // The value at `k` has to be taken from `order[k]`
+   let mut order = vec![0];
+   for &radix in factors {
+      let m = order.len();
+      order = (0..radix)
+         .flat_map(|q| order.iter().map(move |i| q + radix * i))
+         .collect();
+      debug_assert_eq!(order.len(), m * radix);
+   }
+
+   // Follow each cycle of the permutation
+   let mut swaps = vec![];
+   let mut visited = vec![false; order.len()];
+   for start in 0..order.len() {
+      if visited[start] {
+         continue;
+      }
+      let mut k = start;
+      visited[k] = true;
+      while order[k] != start {
+         swaps.push((k, order[k]));
+         k = order[k];
+         visited[k] = true;
+      }
+   }
+   swaps
+}
+
+/// An in-place radix-2 FFT of `data`, whose length must be a power of two.
+///
+/// `twiddles` are `e^(-2πik / period)` for at least the first half of a `period`,
+/// which is a multiple of the length of `data`.
+/// Unlike [`FftPlan`], this does not need the `alloc` feature.
+pub(crate) fn radix2<T: FftFloat>(
+   data: &mut [Complex<T>],
+   twiddles: &[Complex<T>],
+   period: usize,
+   inverse: bool,
 ) {
-   let n = input.len();
+   let len = data.len();
+   debug_assert!(len.is_power_of_two() && period % len == 0);
+
+   let bits = len.trailing_zeros();
+   if bits > 0 {
+      for k in 0..len {
+         let j = k.reverse_bits() >> (usize::BITS - bits);
+         if k < j {
+            data.swap(k, j);
+         }
+      }
+   }
 
-   // check that the input is always a power of two
-   debug_assert!(n.is_power_of_two());
-   debug_assert_eq!(n, output.len());
+   let mut size = 2;
+   while size <= len {
+      for block in data.chunks_exact_mut(size) {
+         butterfly2(block, twiddles, period / size, inverse);
+      }
+      size *= 2;
+   }
+}
 
-   // base case
-   if n == 1 {
-      output[0] = input[0];
-      return;
+/// Combine the two halves of `block` with radix-2 butterflies, using every `stride`-th twiddle.
+fn butterfly2<T: FftFloat>(
+   block: &mut [Complex<T>],
+   twiddles: &[Complex<T>],
+   stride: usize,
+   inverse: bool,
+) {
+   let m = block.len() / 2;
+   let (a, b) = block.split_at_mut(m);
+   for ((x, y), twiddle) in a
+      .iter_mut()
+      .zip(b.iter_mut())
+      .zip(twiddles.iter().step_by(stride))
+   {
+      let twiddle = if inverse { twiddle.conj() } else { *twiddle };
+      let t = *y * twiddle;
+      *y = *x - t;
+      *x = *x + t;
    }
+}
 
-   // Calculate omega
-   let inner_omega = calc_omega(n, is_reverse);
+/// Combine the `radix` parts of `block` with a DFT of size `radix`, which is at most 5.
+#[cfg(feature = "alloc")]
+fn butterfly<T: FftFloat>(
+   block: &mut [Complex<T>],
+   radix: usize,
+   twiddles: &[Complex<T>],
+   inverse: bool,
+) {
+   let m = block.len() / radix;
+   let stride = twiddles.len() / block.len();
+   let twiddle = |k: usize| {
+      let twiddle = twiddles[k];
+      if inverse {
+         twiddle.conj()
+      } else {
+         twiddle
+      }
+   };
+
+   let mut values = [Complex::new(T::zero(), T::zero()); 5];
+   for k in 0..m {
+      for (q, value) in values[..radix].iter_mut().enumerate() {
+         *value = block[q * m + k] * twiddle(q * k * stride);
+      }
 
-   // Inner call
-   let (left, right) = input.splat();
-   do_fft(&left, &mut output[..n / 2], &inner_omega, is_reverse);
-   do_fft(&right, &mut output[n / 2..], &inner_omega, is_reverse);
+      // The roots of unity of the radix are every `m * stride`-th twiddle
+      for p in 0..radix {
+         block[p * m + k] = values[1..radix]
+            .iter()
+            .enumerate()
+            .fold(values[0], |sum, (q, value)| {
+               sum + value * twiddle((p * (q + 1)) % radix * m * stride)
+            });
+      }
+   }
+}
 
-   let mut omega_acc = Complex32::new(1.0, 0.0);
-   for i in 0..n / 2 {
-      let x = output[i] + omega_acc * output[i + n / 2];
-      let y = output[i] - omega_acc * output[i + n / 2];
+/// The unnormalized FFT with the kernel `e^(2πikn / len)`.
+///
+/// This allocates a new [`FftPlan`] on every call, use one directly for repeated transforms.
+#[cfg(feature = "alloc")]
+pub fn fft<T: FftFloat, B: AsRef<[Complex<T>]>>(input: B) -> Result<Vec<Complex<T>>, Error> {
+   let mut output = input.as_ref().to_vec();
+   FftPlan::new(output.len())?.inverse(&mut output)?;
+   Ok(output)
+}
 
-      output[i] = x;
-      output[i + n / 2] = y;
+/// The inverse of [`fft`], normalized such that it returns the original input.
+#[cfg(feature = "alloc")]
+pub fn rfft<T: FftFloat, B: AsRef<[Complex<T>]>>(input: B) -> Result<Vec<Complex<T>>, Error> {
+   let mut output = input.as_ref().to_vec();
+   FftPlan::new(output.len())?.forward(&mut output)?;
 
-      // Update omega for the next round
-      omega_acc = omega_acc * omega;
+   let n = T::from_f64(output.len() as f64);
+   for x in output.iter_mut() {
+      *x = *x / n;
    }
+   Ok(output)
 }
 
-fn calc_omega(n: usize, is_reverse: bool) -> Complex32 {
-   if is_reverse {
-      Complex32::new(0.0, -2.0 * PI / n as f32).exp()
-   } else {
-      Complex32::new(0.0, 2.0 * PI / n as f32).exp()
+/// The complex FFT backing the real transforms.
+#[cfg(feature = "alloc")]
+#[derive(Clone)]
+pub(crate) struct ComplexFft<T = f64> {
+   #[cfg(feature = "rustfft")]
+   fft: alloc::sync::Arc<dyn rustfft::FFT<T>>,
+   #[cfg(not(feature = "rustfft"))]
+   plan: FftPlan<T>,
+   #[cfg(not(feature = "rustfft"))]
+   inverse: bool,
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Sample> ComplexFft<T> {
+   #[cfg(feature = "rustfft")]
+   pub fn new(len: usize, inverse: bool) -> Result<Self, Error> {
+      if len == 0 {
+         return Err(Error::InvalidFrameSize(len));
+      }
+
+      Ok(Self {
+         fft: rustfft::FFTplanner::<T>::new(inverse).plan_fft(len),
+      })
+   }
+
+   #[cfg(not(feature = "rustfft"))]
+   pub fn new(len: usize, inverse: bool) -> Result<Self, Error> {
+      Ok(Self {
+         plan: FftPlan::new(len)?,
+         inverse,
+      })
+   }
+
+   /// Transform `input` into `output`, possibly using `input` as scratch space.
+   ///
+   /// Both need to have the planned length.
+   pub fn process(&mut self, input: &mut [Complex<T>], output: &mut [Complex<T>]) {
+      #[cfg(feature = "rustfft")]
+      self.fft.process(input, output);
+
+      #[cfg(not(feature = "rustfft"))]
+      {
+         output.copy_from_slice(input);
+         let result = if self.inverse {
+            self.plan.inverse(output)
+         } else {
+            self.plan.forward(output)
+         };
+         debug_assert!(result.is_ok());
+      }
    }
 }
 
+/// The forward FFT of real input, computed with a complex FFT of half the length.
+///
+/// The even samples are packed into the real, and the odd samples into the
+/// imaginary part, and the two interleaved spectra are separated afterwards.
+#[cfg(feature = "alloc")]
+pub struct RealFft<T = f64> {
+   len: usize,
+   fft: ComplexFft<T>,
+   twiddles: Vec<Complex<T>>,
+   packed: Vec<Complex<T>>,
+   spectrum: Vec<Complex<T>>,
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Sample> RealFft<T> {
+   /// Plan a transform of `len` samples, which must be even.
+   pub fn new(len: usize) -> Result<Self, Error> {
+      if len < 2 || len % 2 != 0 {
+         return Err(Error::InvalidFrameSize(len));
+      }
+
+      Ok(Self {
+         len,
+         fft: ComplexFft::new(len / 2, false)?,
+         twiddles: twiddles(len, -1.0),
+         packed: vec![Complex::zero(); len / 2],
+         spectrum: vec![Complex::zero(); len / 2],
+      })
+   }
+
+   pub fn len(&self) -> usize {
+      self.len
+   }
+
+   /// Never `true`, as transforms have at least two samples.
+   pub fn is_empty(&self) -> bool {
+      self.len == 0
+   }
+
+   /// Transform `input` of `len` samples into the `len / 2 + 1` bins from DC up to Nyquist.
+   ///
+   /// The output is not normalized, like a complex FFT of the same length.
+   pub fn process(&mut self, input: &[T], output: &mut [Complex<T>]) -> Result<(), Error> {
+      check_len(input.len(), self.len)?;
+      check_len(output.len(), self.len / 2 + 1)?;
+
+      for (z, x) in self.packed.iter_mut().zip(input.chunks_exact(2)) {
+         *z = Complex::new(x[0], x[1]);
+      }
+      self.fft.process(&mut self.packed, &mut self.spectrum);
+
+      split_spectrum(&self.spectrum, &self.twiddles, output);
+
+      Ok(())
+   }
+}
+
+/// The inverse of [`RealFft`], turning `len / 2 + 1` bins back into `len` real samples.
+#[cfg(feature = "alloc")]
+pub struct RealIfft<T = f64> {
+   len: usize,
+   ifft: ComplexFft<T>,
+   twiddles: Vec<Complex<T>>,
+   packed: Vec<Complex<T>>,
+   signal: Vec<Complex<T>>,
+}
+
+#[cfg(feature = "alloc")]
+impl<T: Sample> RealIfft<T> {
+   /// Plan a transform of `len` samples, which must be even.
+   pub fn new(len: usize) -> Result<Self, Error> {
+      if len < 2 || len % 2 != 0 {
+         return Err(Error::InvalidFrameSize(len));
+      }
+
+      Ok(Self {
+         len,
+         ifft: ComplexFft::new(len / 2, true)?,
+         twiddles: twiddles(len, 1.0),
+         packed: vec![Complex::zero(); len / 2],
+         signal: vec![Complex::zero(); len / 2],
+      })
+   }
+
+   pub fn len(&self) -> usize {
+      self.len
+   }
+
+   /// Never `true`, as transforms have at least two samples.
+   pub fn is_empty(&self) -> bool {
+      self.len == 0
+   }
+
+   /// Transform the `len / 2 + 1` bins of `input` into `len` samples.
+   ///
+   /// The upper half of the spectrum is implied to be the mirrored complex
+   /// conjugate of the lower half, and the imaginary parts of the DC and
+   /// Nyquist bins are ignored.
+   /// Like a complex inverse FFT, the output is scaled by `len`.
+   pub fn process(&mut self, input: &[Complex<T>], output: &mut [T]) -> Result<(), Error> {
+      check_len(input.len(), self.len / 2 + 1)?;
+      check_len(output.len(), self.len)?;
+
+      merge_spectrum(input, &self.twiddles, &mut self.packed);
+      self.ifft.process(&mut self.packed, &mut self.signal);
+
+      for (x, z) in output.chunks_exact_mut(2).zip(self.signal.iter()) {
+         x[0] = z.re;
+         x[1] = z.im;
+      }
+
+      Ok(())
+   }
+}
+
+/// Separate the spectra of the even and odd samples packed into the complex
+/// spectrum `z`, and combine them into the spectrum of the real signal.
+///
+/// `twiddles` are `e^(-2πik / 2n)` for `k` from `0` to `n`, where `n` is the length of `z`.
+/// The Nyquist bin is only written if `output` has room for `n + 1` bins.
+pub(crate) fn split_spectrum<T: Sample>(
+   z: &[Complex<T>],
+   twiddles: &[Complex<T>],
+   output: &mut [Complex<T>],
+) {
+   let half = z.len();
+   let one_half = cast::<T>(0.5);
+
+   // DC and Nyquist are the sum and difference of the two real spectra
+   output[0] = Complex::new(z[0].re + z[0].im, T::zero());
+   if let Some(nyquist) = output.get_mut(half) {
+      *nyquist = Complex::new(z[0].re - z[0].im, T::zero());
+   }
+
+   for (((x, a), b), twiddle) in output[1..half]
+      .iter_mut()
+      .zip(z[1..].iter())
+      .zip(z[1..].iter().rev())
+      .zip(twiddles[1..half].iter())
+   {
+      let b = b.conj();
+      let even = (a + b) * one_half;
+      // (a - b) / 2i
+      let diff = a - b;
+      let odd = Complex::new(one_half * diff.im, -one_half * diff.re);
+      *x = even + twiddle * odd;
+   }
+}
+
+/// The inverse of [`split_spectrum`], packing the spectrum of a real signal
+/// into `packed`, whose inverse FFT has the even samples in the real and the
+/// odd samples in the imaginary part.
+///
+/// `twiddles` are `e^(2πik / 2n)` for `k` from `0` to `n`, where `n` is the length of `packed`.
+/// If `input` has no Nyquist bin, it is taken to be zero.
+pub(crate) fn merge_spectrum<T: Sample>(
+   input: &[Complex<T>],
+   twiddles: &[Complex<T>],
+   packed: &mut [Complex<T>],
+) {
+   let half = packed.len();
+   let nyquist = input.get(half).copied().unwrap_or_default();
+   let mirrored = core::iter::once(&nyquist).chain(input[1..half].iter().rev());
+
+   for (((z, a), b), twiddle) in packed
+      .iter_mut()
+      .zip(input[..half].iter())
+      .zip(mirrored)
+      .zip(twiddles.iter())
+   {
+      let b = b.conj();
+      let even = a + b;
+      let odd = (a - b) * twiddle;
+      // even + i * odd
+      *z = Complex::new(even.re - odd.im, even.im + odd.re);
+   }
+}
+
+/// The twiddle factors `e^(sign * 2πik / len)` for `k` from `0` to `len / 2`.
+#[cfg(feature = "alloc")]
+fn twiddles<T: Sample>(len: usize, sign: f64) -> Vec<Complex<T>> {
+   (0..=len / 2)
+      .map(|k| unit(sign * 2.0 * k as f64 / len as f64))
+      .collect()
+}
+
+#[cfg(feature = "alloc")]
+fn check_len(actual: usize, expected: usize) -> Result<(), Error> {
+   if actual != expected {
+      return Err(Error::BufferLength { expected, actual });
+   }
+   Ok(())
+}
+
 #[cfg(test)]
 mod tests {
    use super::*;
    use float_cmp::approx_eq;
-   //use rand::Rng;
+   use num_complex::{Complex32, Complex64};
+   use rand::{Rng, SeedableRng};
 
    macro_rules! complex_approx {
       ($a: expr, $b: expr) => {
@@ -117,28 +648,126 @@ mod tests {
       complex_approx!(output[3], Complex32::new(1.0, 0.0));
    }
 
-   // #[test]
-   // fn fft_inversion() {
-   //     // Get random white noise
-   //     let mut rng = rand::thread_rng();
-   //     let mut test_input = [0; 8192];
-   //     rng.fill(&mut test_input[..]);
-
-   //     // Turn it a complex representation
-   //     let test_input = test_input
-   //         .iter()
-   //         .map(|x| x % (2 << 16))
-   //         .map(|x| x as f32 / (2 << 16) as f32)
-   //         .map(|x| Complex32::new(x, 0.0))
-   //         .collect::<Vec<_>>();
-
-   //     // Do fft
-   //     let fft_output = fft(&test_input).unwrap();
-   //     let rfft_output = rfft(fft_output).unwrap();
-
-   //     for (i, o) in test_input.iter().zip(rfft_output.iter()) {
-   //         dbg!(&i, &o);
-   //         complex_approx!(i, o);
-   //     }
-   // }
+   /// The naive DFT with the kernel `e^(-2πikn / len)`, as a reference.
+   fn dft(input: &[Complex64]) -> Vec<Complex64> {
+      let n = input.len();
+      (0..n)
+         .map(|k| {
+            input
+               .iter()
+               .enumerate()
+               .map(|(j, x)| {
+                  let angle = -2.0 * core::f64::consts::PI * ((j * k) % n) as f64 / n as f64;
+                  x * Complex64::new(angle.cos(), angle.sin())
+               })
+               .sum()
+         })
+         .collect()
+   }
+
+   fn noise(len: usize) -> Vec<Complex64> {
+      let mut rng = rand::rngs::StdRng::seed_from_u64(len as u64);
+      (0..len)
+         .map(|_| Complex64::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)))
+         .collect()
+   }
+
+   #[test]
+   fn plan_matches_dft() {
+      // Powers of two, mixed radices, and primes for Bluestein
+      let sizes = (0..=10)
+         .map(|bits| 1 << bits)
+         .chain(vec![3, 5, 6, 9, 12, 15, 25, 30, 45, 60, 100, 480, 1920])
+         .chain(vec![7, 11, 13, 49, 97, 210, 1001]);
+      for len in sizes {
+         let input = noise(len);
+         let expected = dft(&input);
+
+         let mut plan = FftPlan::<f64>::new(len).unwrap();
+         let mut output = input.clone();
+         plan.forward(&mut output).unwrap();
+         for (a, b) in output.iter().zip(expected.iter()) {
+            assert!(
+               (a - b).norm() < 1e-12 * len as f64,
+               "{}: {} != {}",
+               len,
+               a,
+               b
+            );
+         }
+
+         plan.inverse(&mut output).unwrap();
+         for (a, b) in output.iter().zip(input.iter()) {
+            assert!((a / len as f64 - b).norm() < 1e-14 * len as f64);
+         }
+
+         // Single precision, with the error growing with the logarithm of the size
+         let mut plan = FftPlan::<f32>::new(len).unwrap();
+         let mut output = input
+            .iter()
+            .map(|x| Complex32::new(x.re as f32, x.im as f32))
+            .collect::<Vec<_>>();
+         plan.forward(&mut output).unwrap();
+         let tolerance = 1e-6 * (f64::log2(len as f64) + 1.0) * f64::sqrt(len as f64);
+         for (a, b) in output.iter().zip(expected.iter()) {
+            let a = Complex64::new(a.re as f64, a.im as f64);
+            assert!((a - b).norm() < tolerance, "{}: {} != {}", len, a, b);
+         }
+      }
+   }
+
+   #[test]
+   fn plan_errors() {
+      assert_eq!(
+         FftPlan::<f64>::new(0).err(),
+         Some(Error::InvalidFrameSize(0))
+      );
+
+      let mut plan = FftPlan::<f64>::new(8).unwrap();
+      assert_eq!(
+         plan.forward(&mut [Complex64::new(0.0, 0.0); 4]).err(),
+         Some(Error::BufferLength {
+            expected: 8,
+            actual: 4
+         })
+      );
+   }
+
+   #[test]
+   fn real_fft_matches_complex() {
+      for &len in &[64, 2400, 194] {
+         let input = (0..len)
+            .map(|k| f64::sin(k as f64 * 0.7) + 0.3 * f64::cos(k as f64 * 2.1) + 0.1)
+            .collect::<Vec<_>>();
+
+         let complex = input
+            .iter()
+            .map(|x| Complex64::new(*x, 0.0))
+            .collect::<Vec<_>>();
+         let expected = dft(&complex);
+
+         let mut spectrum = vec![Complex64::new(0.0, 0.0); len / 2 + 1];
+         RealFft::new(len)
+            .unwrap()
+            .process(&input, &mut spectrum)
+            .unwrap();
+         for (a, b) in spectrum.iter().zip(expected.iter()) {
+            assert!((a - b).norm() < 1e-9, "{}: {} != {}", len, a, b);
+         }
+
+         let mut output = vec![0.0; len];
+         RealIfft::new(len)
+            .unwrap()
+            .process(&spectrum, &mut output)
+            .unwrap();
+         for (a, b) in output.iter().zip(input.iter()) {
+            assert!((a / len as f64 - b).abs() < 1e-12);
+         }
+      }
+
+      assert_eq!(
+         RealFft::<f64>::new(7).err(),
+         Some(Error::InvalidFrameSize(7))
+      );
+   }
 }
@@ -0,0 +1,405 @@
+//! Analysis and resynthesis with frames of a fixed size, that never allocate.
+//!
+//! The types mirror [`Frequencer`](crate::Frequencer), [`Resynth`](crate::Resynth)
+//! and [`Wavelet`](crate::Wavelet), but keep all their buffers in arrays.
+//! This makes them usable without an allocator, e.g. on microcontrollers or
+//! in audio threads, and they work without the `alloc` feature.
+//!
+//! As array lengths can not be calculated from const generics yet, `N` is the
+//! number of bins, and frames are `2 * N` samples long.
+//! `N` needs to be a power of two.
+//! Like their heap based counterparts, they work in `f64` by default, or in `f32`.
+
+use num_complex::Complex;
+use num_traits::Zero;
+
+use crate::{
+   cast,
+   error::check_frame,
+   fft::{merge_spectrum, radix2, split_spectrum, unit},
+   framing::Framing,
+   shift_bins_fast,
+   window::overlap_normalization,
+   Error, FrequencyBin, Sample, Window,
+};
+
+/// The spectrum of a frame of `2 * N` samples, as `N` bins from DC up to below Nyquist.
+#[derive(Debug, Clone)]
+pub struct Wavelet<const N: usize, T = f64> {
+   pub bins: [FrequencyBin<T>; N],
+}
+
+impl<const N: usize, T: Sample> Wavelet<N, T> {
+   pub fn empty() -> Self {
+      Self {
+         bins: core::array::from_fn(|_| FrequencyBin::zero()),
+      }
+   }
+
+   /// The frequency of the loudest bin, like [`Wavelet::base_freq`](crate::Wavelet::base_freq).
+   pub fn base_freq(&self) -> T {
+      let mut max_freq = T::zero();
+      let mut max_amp = T::zero();
+      for bin in &self.bins {
+         if bin.amplitude > max_amp {
+            max_amp = bin.amplitude;
+            max_freq = bin.frequency;
+         }
+      }
+      max_freq
+   }
+
+   /// Shift the pitch by moving every bin to the nearest lower bin, like
+   /// [`ShiftMode::Fast`](crate::ShiftMode::Fast).
+   pub fn pitch_shift(&mut self, pitch_shift: f64) {
+      let mut shifted = Self::empty();
+      shift_bins_fast(&self.bins, &mut shifted.bins, pitch_shift);
+      *self = shifted;
+   }
+}
+
+impl<const N: usize, T: Sample> Default for Wavelet<N, T> {
+   fn default() -> Self {
+      Self::empty()
+   }
+}
+
+#[cfg(feature = "alloc")]
+impl<const N: usize, T: Sample> From<Wavelet<N, T>> for crate::Wavelet<T> {
+   fn from(wavelet: Wavelet<N, T>) -> Self {
+      crate::Wavelet {
+         bins: wavelet.bins.into(),
+      }
+   }
+}
+
+#[cfg(feature = "alloc")]
+impl<const N: usize, T: Sample> core::convert::TryFrom<&crate::Wavelet<T>> for Wavelet<N, T> {
+   type Error = Error;
+
+   fn try_from(wavelet: &crate::Wavelet<T>) -> Result<Self, Error> {
+      if wavelet.bins.len() != N {
+         return Err(Error::WaveletSize {
+            expected: N,
+            actual: wavelet.bins.len(),
+         });
+      }
+
+      let mut fixed = Self::empty();
+      fixed.bins.clone_from_slice(&wavelet.bins);
+      Ok(fixed)
+   }
+}
+
+/// Checks the const parameters and calculates the twiddles `e^(sign * 2πik / 2N)`.
+fn setup<const N: usize, const HOP: usize, T: Sample>(sign: f64) -> Result<[Complex<T>; N], Error> {
+   if !N.is_power_of_two() {
+      return Err(Error::InvalidFrameSize(2 * N));
+   }
+   check_frame(2 * N, HOP)?;
+
+   Ok(core::array::from_fn(|k| unit(sign * k as f64 / N as f64)))
+}
+
+/// Move the samples stored in pairs `hop` samples towards the start.
+fn shift_pairs<T: Copy>(pairs: &mut [[T; 2]], hop: usize) {
+   for i in 0..2 * pairs.len() - hop {
+      pairs[i / 2][i % 2] = pairs[(i + hop) / 2][(i + hop) % 2];
+   }
+}
+
+/// The window for a frame of `2 * N` samples, as pairs of samples.
+fn window_pairs<const N: usize, T: Sample>(window: Window) -> [[T; 2]; N] {
+   core::array::from_fn(|k| {
+      [
+         cast(window.value(2 * k, 2 * N)),
+         cast(window.value(2 * k + 1, 2 * N)),
+      ]
+   })
+}
+
+/// Like [`Frequencer`](crate::Frequencer), with frames of `2 * N` samples and
+/// a step size of `HOP` samples.
+pub struct Frequencer<const N: usize, const HOP: usize, T = f64> {
+   sample_rate: usize,
+   framing: Framing,
+   window: Window,
+   // The samples are kept in pairs, which are packed into one complex value for the FFT
+   window_table: [[T; 2]; N],
+   sample_buf: [[T; 2]; N],
+   phase_buf: [T; N],
+   twiddles: [Complex<T>; N],
+   packed: [Complex<T>; N],
+   spectrum: [Complex<T>; N],
+}
+
+impl<const N: usize, const HOP: usize, T: Sample> Frequencer<N, HOP, T> {
+   pub fn new(sample_rate: usize) -> Result<Self, Error> {
+      Self::with_window(sample_rate, Window::Hann)
+   }
+
+   pub fn with_window(sample_rate: usize, window: Window) -> Result<Self, Error> {
+      Ok(Self {
+         twiddles: setup::<N, HOP, T>(-1.0)?,
+         sample_rate,
+         framing: Framing::new(sample_rate, 2 * N, HOP),
+         window,
+         window_table: window_pairs(window),
+         sample_buf: [[T::zero(); 2]; N],
+         phase_buf: [T::zero(); N],
+         packed: [Complex::zero(); N],
+         spectrum: [Complex::zero(); N],
+      })
+   }
+
+   pub fn sample_rate(&self) -> usize {
+      self.sample_rate
+   }
+
+   pub fn window(&self) -> Window {
+      self.window
+   }
+
+   /// Feed a step of audio and get the resulting [`Wavelet`].
+   pub fn feed_audio(&mut self, audio: &[T; HOP]) -> Wavelet<N, T> {
+      let mut wavelet = Wavelet::empty();
+      self.feed_audio_into(audio, &mut wavelet);
+      wavelet
+   }
+
+   /// Like [`Frequencer::feed_audio`], but writes into an existing wavelet.
+   pub fn feed_audio_into(&mut self, audio: &[T; HOP], wavelet: &mut Wavelet<N, T>) {
+      // Move the buffer by a step and add the new audio to the end
+      shift_pairs(&mut self.sample_buf, HOP);
+      for (i, x) in (2 * N - HOP..).zip(audio.iter()) {
+         self.sample_buf[i / 2][i % 2] = *x;
+      }
+
+      // apply windowing, packing the even and odd samples into one complex value
+      for ((z, x), window) in self
+         .packed
+         .iter_mut()
+         .zip(self.sample_buf.iter())
+         .zip(self.window_table.iter())
+      {
+         *z = Complex::new(window[0] * x[0], window[1] * x[1]);
+      }
+
+      radix2(&mut self.packed, &self.twiddles, 2 * N, false);
+      split_spectrum(&self.packed, &self.twiddles, &mut self.spectrum);
+
+      self
+         .framing
+         .analyse(&self.spectrum, &mut self.phase_buf, &mut wavelet.bins);
+   }
+}
+
+/// Like [`Resynth`](crate::Resynth), with frames of `2 * N` samples and a
+/// step size of `HOP` samples.
+///
+/// Every bin accumulates its phase independently, like with
+/// [`PhaseLocking::Off`](crate::PhaseLocking::Off).
+pub struct Resynth<const N: usize, const HOP: usize, T = f64> {
+   sample_rate: usize,
+   framing: Framing,
+   window: Window,
+   window_table: [[T; 2]; N],
+   normalization: [T; HOP],
+   sample_buf: [[T; 2]; N],
+   phase_buf: [T; N],
+   started: bool,
+   // The twiddles of the inverse transform
+   twiddles: [Complex<T>; N],
+   spectrum: [Complex<T>; N],
+   packed: [Complex<T>; N],
+}
+
+impl<const N: usize, const HOP: usize, T: Sample> Resynth<N, HOP, T> {
+   pub fn new(sample_rate: usize) -> Result<Self, Error> {
+      Self::with_window(sample_rate, Window::Hann)
+   }
+
+   pub fn with_window(sample_rate: usize, window: Window) -> Result<Self, Error> {
+      let twiddles = setup::<N, HOP, T>(1.0)?;
+      let window_table = window_pairs(window);
+      let table = window_pairs::<N, f64>(window);
+      let normalization = core::array::from_fn(|position| {
+         cast(overlap_normalization(table.iter().flatten(), HOP, position))
+      });
+
+      Ok(Self {
+         sample_rate,
+         framing: Framing::new(sample_rate, 2 * N, HOP),
+         window,
+         window_table,
+         normalization,
+         sample_buf: [[T::zero(); 2]; N],
+         phase_buf: [T::zero(); N],
+         started: false,
+         twiddles,
+         spectrum: [Complex::zero(); N],
+         packed: [Complex::zero(); N],
+      })
+   }
+
+   pub fn sample_rate(&self) -> usize {
+      self.sample_rate
+   }
+
+   pub fn window(&self) -> Window {
+      self.window
+   }
+
+   /// The number of samples the output lags behind the input of a [`Frequencer`]
+   /// with the same parameters, which is exactly the frame size `2 * N`.
+   pub fn latency(&self) -> usize {
+      2 * N
+   }
+
+   /// Synthesize `wavelet` and pull the next step of audio.
+   pub fn pull_audio(&mut self, audio: &mut [T; HOP], wavelet: &Wavelet<N, T>) {
+      if self.started {
+         self.framing.advance(&wavelet.bins, &mut self.phase_buf);
+      } else {
+         self.framing.start(&wavelet.bins, &mut self.phase_buf);
+         self.started = true;
+      }
+
+      // turn back into complex numbers, the Nyquist bin is not part of the wavelet
+      for ((x, bin), phase) in self
+         .spectrum
+         .iter_mut()
+         .zip(wavelet.bins.iter())
+         .zip(self.phase_buf.iter())
+      {
+         *x = Complex::from_polar(bin.amplitude, *phase);
+      }
+
+      // reverse fft, getting the even samples in the real and the odd ones in the imaginary part
+      merge_spectrum(&self.spectrum, &self.twiddles, &mut self.packed);
+      radix2(&mut self.packed, &self.twiddles, 2 * N, false);
+
+      // drain buffer into audio output normalizing the overlap of the windows,
+      // then move it by a step and fill up with zeros
+      for ((y, x), normalization) in audio
+         .iter_mut()
+         .zip(self.sample_buf.iter().flatten())
+         .zip(self.normalization.iter())
+      {
+         *y = *x * *normalization;
+      }
+      shift_pairs(&mut self.sample_buf, HOP);
+      for i in 2 * N - HOP..2 * N {
+         self.sample_buf[i / 2][i % 2] = T::zero();
+      }
+
+      // apply window and accumulate output to buffer
+      let scale = cast::<T>(1.0 / (2 * N) as f64);
+      for ((y, z), window) in self
+         .sample_buf
+         .iter_mut()
+         .zip(self.packed.iter())
+         .zip(self.window_table.iter())
+      {
+         y[0] = y[0] + window[0] * z.re * scale;
+         y[1] = y[1] + window[1] * z.im * scale;
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use core::{convert::TryFrom, f64::consts::PI};
+
+   fn sine(frequency: f64, start: usize) -> [f64; 256] {
+      core::array::from_fn(|n| f64::sin(2.0 * PI * frequency * (start + n) as f64 / 48000.0))
+   }
+
+   #[test]
+   fn matches_heap_types() {
+      let mut fixed = Frequencer::<512, 256>::new(48000).unwrap();
+      let mut heap = crate::Frequencer::new(48000, 1024, 256).unwrap();
+      let mut fixed_resynth = Resynth::<512, 256>::new(48000).unwrap();
+      let mut heap_resynth = crate::Resynth::new(48000, 1024, 256).unwrap();
+
+      for k in 0..16 {
+         let audio = sine(1000.0, k * 256);
+         let wavelet = fixed.feed_audio(&audio);
+         let expected = heap.feed_audio(&audio);
+         for (a, b) in wavelet.bins.iter().zip(expected.bins.iter()) {
+            assert!((a.amplitude - b.amplitude).abs() < 1e-9);
+            // The phases of the leakage are only numerical noise
+            if b.amplitude > 1e-3 {
+               assert!((a.frequency - b.frequency).abs() < 1e-6);
+            }
+         }
+
+         let mut output = [0.0; 256];
+         fixed_resynth.pull_audio(&mut output, &wavelet);
+         let mut expected_output = [0.0; 256];
+         heap_resynth.pull_audio(&mut expected_output, Some(expected));
+         for (a, b) in output.iter().zip(expected_output.iter()) {
+            assert!((a - b).abs() < 1e-9);
+         }
+      }
+   }
+
+   #[test]
+   fn f32_matches_f64() {
+      let mut freq = Frequencer::<512, 256>::new(48000).unwrap();
+      let mut single = Frequencer::<512, 256, f32>::new(48000).unwrap();
+      let mut resynth = Resynth::<512, 256>::new(48000).unwrap();
+      let mut single_resynth = Resynth::<512, 256, f32>::new(48000).unwrap();
+
+      for k in 0..16 {
+         let audio = sine(1000.0, k * 256);
+         let mut expected = [0.0; 256];
+         resynth.pull_audio(&mut expected, &freq.feed_audio(&audio));
+
+         let audio = audio.map(|x| x as f32);
+         let mut output = [0.0; 256];
+         single_resynth.pull_audio(&mut output, &single.feed_audio(&audio));
+         for (a, b) in output.iter().zip(expected.iter()) {
+            assert!((*a as f64 - b).abs() < 1e-4);
+         }
+      }
+   }
+
+   #[test]
+   fn conversions() {
+      let mut wavelet = Wavelet::<4>::empty();
+      wavelet.bins[1].amplitude = 1.0;
+      wavelet.bins[1].frequency = 100.0;
+
+      let heap = crate::Wavelet::from(wavelet.clone());
+      assert_eq!(heap.bins.len(), 4);
+      assert_eq!(Wavelet::<4>::try_from(&heap).unwrap().base_freq(), 100.0);
+      assert_eq!(
+         Wavelet::<8>::try_from(&heap).err(),
+         Some(Error::WaveletSize {
+            expected: 8,
+            actual: 4
+         })
+      );
+
+      wavelet.pitch_shift(2.0);
+      assert_eq!(wavelet.base_freq(), 200.0);
+      assert_eq!(wavelet.bins[2].amplitude, 1.0);
+   }
+
+   #[test]
+   fn invalid_sizes() {
+      assert_eq!(
+         Frequencer::<12, 4>::new(48000).err(),
+         Some(Error::InvalidFrameSize(24))
+      );
+      assert_eq!(
+         Resynth::<8, 16>::new(48000).err(),
+         Some(Error::InvalidStepSize {
+            step_size: 16,
+            frame_size: 16
+         })
+      );
+   }
+}
@@ -0,0 +1,108 @@
+use num_complex::Complex;
+
+use crate::{cast, FrequencyBin, Sample};
+
+/// The relation between bin phases and frequencies for a frame and step size.
+///
+/// Shared by the heap based and the [`fixed`](crate::fixed) analysis and resynthesis.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Framing {
+   freqs_per_bin: f64,
+   phase_diff_per_frame: f64,
+   oversampling_rate: f64,
+}
+
+impl Framing {
+   pub fn new(sample_rate: usize, frame_size: usize, step_size: usize) -> Self {
+      Self {
+         freqs_per_bin: sample_rate as f64 / frame_size as f64,
+         phase_diff_per_frame: 2.0 * core::f64::consts::PI * step_size as f64 / frame_size as f64,
+         oversampling_rate: frame_size as f64 / step_size as f64,
+      }
+   }
+
+   /// Turn the `spectrum` of a frame into `bins`, estimating their frequencies
+   /// from the phase difference to the prior frame in `phase_buf`.
+   pub fn analyse<T: Sample>(
+      &self,
+      spectrum: &[Complex<T>],
+      phase_buf: &mut [T],
+      bins: &mut [FrequencyBin<T>],
+   ) {
+      let (freqs_per_bin, phase_diff_per_frame, oversampling_rate) = self.factors::<T>();
+      for (k, ((bin, x), last_phase)) in bins
+         .iter_mut()
+         .zip(spectrum.iter())
+         .zip(phase_buf.iter_mut())
+         .enumerate()
+      {
+         let (amp, phase) = x.to_polar();
+
+         // get the phase difference to prior frame and remember the current phase
+         let mut phase_diff = phase - *last_phase;
+         *last_phase = phase;
+
+         // calculate difference to expected phase and map it back onto [-PI, PI]
+         phase_diff = phase_diff - index::<T>(k) * phase_diff_per_frame;
+         phase_diff = principal_arg(phase_diff);
+
+         // compute frequency deviation
+         let freq_dev = oversampling_rate * phase_diff / T::TAU();
+
+         // compute frequency
+         let freq = (index::<T>(k) + freq_dev) * freqs_per_bin;
+
+         *bin = FrequencyBin {
+            amplitude: amp,
+            frequency: freq,
+            phase,
+         };
+      }
+   }
+
+   /// Start the synthesis phases in `phase_buf` at the analysis phases of `bins`,
+   /// such that unmodified wavelets are resynthesized exactly.
+   pub fn start<T: Sample>(&self, bins: &[FrequencyBin<T>], phase_buf: &mut [T]) {
+      for (bin, phase) in bins.iter().zip(phase_buf.iter_mut()) {
+         *phase = bin.phase;
+      }
+   }
+
+   /// Advance the synthesis phases in `phase_buf` by one step, according to
+   /// the frequencies of `bins`.
+   pub fn advance<T: Sample>(&self, bins: &[FrequencyBin<T>], phase_buf: &mut [T]) {
+      let (freqs_per_bin, phase_diff_per_frame, oversampling_rate) = self.factors::<T>();
+      for (k, (bin, phase)) in bins.iter().zip(phase_buf.iter_mut()).enumerate() {
+         // calculate frequency deviation from frequency
+         let freq_dev = (bin.frequency - index::<T>(k) * freqs_per_bin) / freqs_per_bin;
+
+         // calculate phase difference from frequency deviation
+         let mut phase_diff = T::TAU() * freq_dev / oversampling_rate;
+
+         // add possible overlap
+         phase_diff = phase_diff + index::<T>(k) * phase_diff_per_frame;
+
+         // add phase diff to output phase buffer, wrapped to keep its precision in long streams
+         *phase = principal_arg(*phase + phase_diff);
+      }
+   }
+
+   /// The bin width, expected phase advance of the first bin and oversampling rate as `T`.
+   fn factors<T: Sample>(&self) -> (T, T, T) {
+      (
+         cast(self.freqs_per_bin),
+         cast(self.phase_diff_per_frame),
+         cast(self.oversampling_rate),
+      )
+   }
+}
+
+/// The bin index `k` as `T`.
+fn index<T: Sample>(k: usize) -> T {
+   cast(k as f64)
+}
+
+/// Wrap `phase` into the range from `-PI` to `PI`.
+fn principal_arg<T: Sample>(phase: T) -> T {
+   phase - T::TAU() * (phase / T::TAU()).round()
+}
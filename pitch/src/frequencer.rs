@@ -1,42 +1,52 @@
-use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
-use core::{f64::consts::PI, iter::FromIterator};
-use num_complex::Complex64;
-use rustfft::{FFTplanner, FFT};
+use alloc::vec::Vec;
+use num_complex::Complex;
+use num_traits::Zero;
 
-use crate::{FrequencyBin, Wavelet};
+use crate::{
+   cast, error::check_frame, fft::RealFft, framing::Framing, Error, FrequencyBin, Sample, Wavelet,
+   Window,
+};
 
-pub struct Frequencer {
+/// Analyses audio step by step into [`Wavelet`]s, in `f64` or `f32` precision.
+pub struct Frequencer<T = f64> {
    sample_rate: usize,
    frame_size: usize,
    step_size: usize,
-   freqs_per_bin: f64,
-   phase_diff_per_frame: f64,
-   oversampling_rate: f64,
-   sample_buf: VecDeque<f64>,
-   phase_buf: Vec<f64>,
-   fft: Arc<dyn FFT<f64>>,
+   framing: Framing,
+   window: Window,
+   window_table: Vec<T>,
+   sample_buf: Vec<T>,
+   phase_buf: Vec<T>,
+   frame: Vec<T>,
+   spectrum: Vec<Complex<T>>,
+   fft: RealFft<T>,
 }
 
-impl Frequencer {
-   pub fn new(sample_rate: usize, frame_size: usize, step_size: usize) -> Result<Self, ()> {
-      if !frame_size.is_power_of_two() {
-         return Err(());
-      }
+impl<T: Sample> Frequencer<T> {
+   pub fn new(sample_rate: usize, frame_size: usize, step_size: usize) -> Result<Self, Error> {
+      Self::with_window(sample_rate, frame_size, step_size, Window::Hann)
+   }
 
-      if step_size >= frame_size {
-         return Err(());
-      }
+   pub fn with_window(
+      sample_rate: usize,
+      frame_size: usize,
+      step_size: usize,
+      window: Window,
+   ) -> Result<Self, Error> {
+      check_frame(frame_size, step_size)?;
 
       Ok(Self {
          sample_rate,
          frame_size,
          step_size,
-         freqs_per_bin: sample_rate as f64 / frame_size as f64,
-         phase_diff_per_frame: 2.0 * PI * step_size as f64 / frame_size as f64,
-         oversampling_rate: frame_size as f64 / step_size as f64,
-         sample_buf: VecDeque::from_iter(core::iter::repeat(0.0).take(frame_size)),
-         phase_buf: vec![0.0; frame_size],
-         fft: FFTplanner::new(false).plan_fft(frame_size),
+         framing: Framing::new(sample_rate, frame_size, step_size),
+         window,
+         window_table: window.table(frame_size).into_iter().map(cast).collect(),
+         sample_buf: vec![T::zero(); frame_size],
+         phase_buf: vec![T::zero(); frame_size / 2],
+         frame: vec![T::zero(); frame_size],
+         spectrum: vec![Complex::zero(); frame_size / 2 + 1],
+         fft: RealFft::new(frame_size)?,
       })
    }
 
@@ -44,89 +54,104 @@ impl Frequencer {
       self.sample_rate
    }
 
+   pub fn frame_size(&self) -> usize {
+      self.frame_size
+   }
+
    pub fn step_size(&self) -> usize {
       self.step_size
    }
 
-   pub fn feed_audio(&mut self, audio: &[f64]) -> Wavelet {
+   /// Change the step size, i.e. the analysis hop, between two frames.
+   ///
+   /// Combined with a [`Resynth`](crate::Resynth) running at a different step size,
+   /// this changes the speed of the audio without changing its pitch.
+   pub fn set_step_size(&mut self, step_size: usize) -> Result<(), Error> {
+      check_frame(self.frame_size, step_size)?;
+
+      self.step_size = step_size;
+      self.framing = Framing::new(self.sample_rate, self.frame_size, step_size);
+      Ok(())
+   }
+
+   pub fn window(&self) -> Window {
+      self.window
+   }
+
+   /// Feed exactly `step_size` samples of audio and get the resulting [`Wavelet`].
+   ///
+   /// # Panics
+   /// Panics if `audio` is not exactly `step_size` samples long.
+   /// Use [`Frequencer::try_feed_audio`] to handle this case instead.
+   pub fn feed_audio(&mut self, audio: &[T]) -> Wavelet<T> {
+      match self.try_feed_audio(audio) {
+         Ok(wavelet) => wavelet,
+         Err(err) => panic!("{}", err),
+      }
+   }
+
+   /// Like [`Frequencer::feed_audio`] but returns an error instead of panicking.
+   pub fn try_feed_audio(&mut self, audio: &[T]) -> Result<Wavelet<T>, Error> {
+      let mut wavelet = Wavelet::empty(0);
+      self.try_feed_audio_into(audio, &mut wavelet)?;
+      Ok(wavelet)
+   }
+
+   /// Like [`Frequencer::feed_audio`], but writes into an existing wavelet.
+   ///
+   /// Once `wavelet` has `frame_size / 2` bins, this does not allocate.
+   ///
+   /// # Panics
+   /// Panics if `audio` is not exactly `step_size` samples long.
+   /// Use [`Frequencer::try_feed_audio_into`] to handle this case instead.
+   pub fn feed_audio_into(&mut self, audio: &[T], wavelet: &mut Wavelet<T>) {
+      if let Err(err) = self.try_feed_audio_into(audio, wavelet) {
+         panic!("{}", err);
+      }
+   }
+
+   /// Like [`Frequencer::feed_audio_into`] but returns an error instead of panicking.
+   pub fn try_feed_audio_into(
+      &mut self,
+      audio: &[T],
+      wavelet: &mut Wavelet<T>,
+   ) -> Result<(), Error> {
       // We can only accept slices that are exact step size long
-      assert_eq!(audio.len(), self.step_size);
-
-      // Add the new audio to the end of the buffer
-      self.sample_buf.extend(audio.iter());
-      self.sample_buf.drain(..self.step_size());
-      debug_assert_eq!(self.sample_buf.len(), self.frame_size);
-
-      let mut frame = self
-         .sample_buf
-         .iter()
-         // apply windowing
-         .enumerate()
-         .map(|(k, x)| {
-            let window = -0.5 * f64::cos(2.0 * PI * k as f64 / self.frame_size as f64) + 0.5;
-            window * x
-         })
-         // map to complex numbers
-         .map(|x| Complex64::new(x, 0.0))
-         .collect::<Vec<_>>();
+      if audio.len() != self.step_size {
+         return Err(Error::BufferLength {
+            expected: self.step_size,
+            actual: audio.len(),
+         });
+      }
 
-      // do the actual transformation
-      //let fft1 = crate::fft::fft(&frame).unwrap();
-      use rustfft::num_traits::Zero;
-      let mut fft = vec![Complex64::zero(); self.frame_size];
-      self.fft.process(&mut frame, &mut fft);
-
-      // for i in 0..self.frame_size {
-      //    assert_eq!(fft1[i], fft[i]);
-      // }
-
-      // transform
-      let bins = fft
-         .iter()
-         // FFT is symetric, therefore we only need lower half
-         .take(self.frame_size / 2)
-         // transform into polar
-         // now r is amplitutde and theta is phase
-         .map(|x| x.to_polar())
-         .enumerate()
-         .map(|(k, (amp, phase))| {
-            // get the phase difference to prior frame and update
-            let mut phase_diff = phase - self.phase_buf[k];
-            self.phase_buf[k] = phase_diff;
-
-            // calculate difference to expected phase
-            phase_diff -= k as f64 * self.phase_diff_per_frame;
-
-            let n = (f64::abs(phase_diff) / PI) as usize;
-
-            // map back onto rad
-            if phase_diff > 0.0 {
-               phase_diff -= n as f64 * PI;
-               if phase_diff > PI {
-                  phase_diff = PI;
-               }
-            } else {
-               phase_diff += n as f64 * PI;
-               if phase_diff < -PI {
-                  phase_diff = -PI;
-               }
-            }
-            assert!(phase_diff <= PI && phase_diff >= -PI);
+      // Move the buffer by a step and add the new audio to the end
+      self.sample_buf.copy_within(self.step_size.., 0);
+      let start = self.frame_size - self.step_size;
+      self.sample_buf[start..].copy_from_slice(audio);
 
-            // compute frequency deviation
-            let freq_dev = self.oversampling_rate * phase_diff / (2.0 * PI);
+      // apply windowing
+      for ((y, x), window) in self
+         .frame
+         .iter_mut()
+         .zip(self.sample_buf.iter())
+         .zip(self.window_table.iter())
+      {
+         *y = *window * *x;
+      }
 
-            // compute frequency
-            let freq = (k as f64 + freq_dev) * self.freqs_per_bin;
+      // do the actual transformation
+      self.fft.process(&self.frame, &mut self.spectrum)?;
 
-            FrequencyBin {
-               amplitude: amp,
-               frequency: freq,
-            }
-         })
-         .collect::<Vec<_>>();
+      // transform into polar, leaving out the Nyquist bin
+      // now r is amplitutde and theta is phase
+      wavelet
+         .bins
+         .resize(self.frame_size / 2, FrequencyBin::zero());
+      self
+         .framing
+         .analyse(&self.spectrum, &mut self.phase_buf, &mut wavelet.bins);
 
-      Wavelet { bins }
+      Ok(())
    }
 }
 
@@ -135,6 +160,7 @@ mod tests {
    use super::*;
    use alloc::rc::Rc;
    use core::cell::RefCell;
+   use core::f64::consts::PI;
    use fon::{chan::Channel, mono::Mono64, Sample, Sink};
    use twang::Synth;
 
@@ -162,6 +188,7 @@ mod tests {
             let buf = cell.buf.clone();
             let wv = cell.freq.feed_audio(&buf);
             cell.current_wavelet = Some(wv);
+            cell.buf.clear();
          }
       }
 
@@ -191,6 +218,236 @@ mod tests {
          .unwrap()
          .base_freq();
 
-      assert!(base_freq >= 995.0 && base_freq <= 1005.0);
+      assert!((995.0..=1005.0).contains(&base_freq));
+   }
+
+   #[test]
+   fn millisecond_frames() {
+      // 40 ms frames with 10 ms steps, which are no powers of two
+      let mut freq = Frequencer::new(48000, 1920, 480).unwrap();
+      let mut wavelet = Wavelet::empty(0);
+      for k in 0..8 {
+         let audio = (0..480)
+            .map(|n| f64::sin(2.0 * PI * 1000.0 * (k * 480 + n) as f64 / 48000.0))
+            .collect::<Vec<_>>();
+         freq.feed_audio_into(&audio, &mut wavelet);
+      }
+
+      assert_eq!(wavelet.bins.len(), 960);
+      assert!((995.0..=1005.0).contains(&wavelet.base_freq()));
+   }
+
+   #[test]
+   fn feed_into_reuses_wavelet() {
+      let mut freq = Frequencer::new(48000, 1024, 256).unwrap();
+      let mut other = Frequencer::new(48000, 1024, 256).unwrap();
+
+      let mut wavelet = Wavelet::empty(512);
+      let bins = wavelet.bins.as_ptr();
+      for k in 0..8 {
+         let audio = (0..256)
+            .map(|n| f64::sin((k * 256 + n) as f64 * 0.1))
+            .collect::<Vec<_>>();
+         freq.feed_audio_into(&audio, &mut wavelet);
+         let expected = other.feed_audio(&audio);
+
+         assert_eq!(wavelet.bins.as_ptr(), bins);
+         for (a, b) in wavelet.bins.iter().zip(expected.bins.iter()) {
+            assert_eq!(a.amplitude, b.amplitude);
+            assert_eq!(a.frequency, b.frequency);
+         }
+      }
+   }
+
+   #[test]
+   fn frequencer_errors() {
+      assert_eq!(
+         Frequencer::<f64>::new(48000, 2001, 256).err(),
+         Some(Error::InvalidFrameSize(2001))
+      );
+      assert_eq!(
+         Frequencer::<f64>::new(48000, 2048, 2048).err(),
+         Some(Error::InvalidStepSize {
+            step_size: 2048,
+            frame_size: 2048
+         })
+      );
+
+      let mut freq = Frequencer::new(48000, 2048, 256).unwrap();
+      assert_eq!(
+         freq.try_feed_audio(&[0.0; 100]).err(),
+         Some(Error::BufferLength {
+            expected: 256,
+            actual: 100
+         })
+      );
+      assert!(freq.try_feed_audio(&[0.0; 256]).is_ok());
+   }
+
+   /// The frame and step sizes the accuracy tests run with.
+   const CONFIGS: [(usize, usize); 5] = [
+      (1024, 256),
+      (2048, 256),
+      (2048, 512),
+      (1920, 480),
+      (4096, 1024),
+   ];
+
+   /// Analyse `frames` frames of `signal`, a function of the time in seconds.
+   fn analyse(
+      frame_size: usize,
+      step_size: usize,
+      frames: usize,
+      signal: impl Fn(f64) -> f64,
+   ) -> Vec<Wavelet> {
+      let mut freq = Frequencer::new(48000, frame_size, step_size).unwrap();
+      (0..frames)
+         .map(|j| {
+            let audio = (0..step_size)
+               .map(|n| signal((j * step_size + n) as f64 / 48000.0))
+               .collect::<Vec<_>>();
+            freq.feed_audio(&audio)
+         })
+         .collect()
+   }
+
+   /// The indices of the local maxima above `threshold` times the loudest bin.
+   fn peaks(wavelet: &Wavelet, threshold: f64) -> Vec<usize> {
+      let bins = &wavelet.bins;
+      let max = bins.iter().map(|bin| bin.amplitude).fold(0.0, f64::max);
+      (1..bins.len() - 1)
+         .filter(|&k| {
+            bins[k].amplitude > threshold * max
+               && bins[k].amplitude > bins[k - 1].amplitude
+               && bins[k].amplitude >= bins[k + 1].amplitude
+         })
+         .collect()
+   }
+
+   #[test]
+   fn off_bin_sines() {
+      for &(frame_size, step_size) in CONFIGS.iter() {
+         let freqs_per_bin = 48000.0 / frame_size as f64;
+         for &frequency in [440.0, 1000.3, 3333.3, 25.5 * freqs_per_bin, 7000.0].iter() {
+            let frames = frame_size / step_size + 4;
+            let wavelets = analyse(frame_size, step_size, frames, |t| {
+               0.5 * f64::sin(2.0 * PI * frequency * t + 0.3)
+            });
+
+            // The whole main lobe agrees on the frequency of the sine
+            let wavelet = wavelets.last().unwrap();
+            let peak = peaks(wavelet, 0.5)[0];
+            for k in peak - 1..=peak + 1 {
+               let estimate = wavelet.bins[k].frequency;
+               assert!(
+                  (estimate - frequency).abs() < 0.1,
+                  "{}/{}: {} Hz in bin {} estimated as {} Hz",
+                  frame_size,
+                  step_size,
+                  frequency,
+                  k,
+                  estimate
+               );
+            }
+         }
+      }
+   }
+
+   #[test]
+   fn multi_tone() {
+      let tones = [(220.0, 0.5), (1234.5, 0.3), (5000.0, 0.1), (9876.5, 0.05)];
+      for &(frame_size, step_size) in CONFIGS.iter() {
+         let frames = frame_size / step_size + 4;
+         let wavelets = analyse(frame_size, step_size, frames, |t| {
+            tones
+               .iter()
+               .map(|(frequency, amplitude)| amplitude * f64::sin(2.0 * PI * frequency * t))
+               .sum()
+         });
+
+         let wavelet = wavelets.last().unwrap();
+         let peaks = peaks(wavelet, 0.01);
+         assert_eq!(peaks.len(), tones.len(), "{}/{}", frame_size, step_size);
+         for (&k, (frequency, _)) in peaks.iter().zip(tones.iter()) {
+            let estimate = wavelet.bins[k].frequency;
+            assert!(
+               (estimate - frequency).abs() < 0.05,
+               "{}/{}: {} Hz estimated as {} Hz",
+               frame_size,
+               step_size,
+               frequency,
+               estimate
+            );
+         }
+      }
+   }
+
+   #[test]
+   fn chirp() {
+      // A linear chirp from 300 Hz upwards by 200 Hz per second
+      let (start, rate) = (300.0, 200.0);
+      for &(frame_size, step_size) in CONFIGS.iter() {
+         let frames = 48000 / step_size;
+         let wavelets = analyse(frame_size, step_size, frames, |t| {
+            0.5 * f64::sin(2.0 * PI * (start * t + 0.5 * rate * t * t))
+         });
+
+         for (j, wavelet) in wavelets.iter().enumerate().skip(frame_size / step_size + 1) {
+            // The phase advances between the centres of the last two frames
+            let centre = ((j + 1) * step_size) as f64 - (frame_size + step_size) as f64 / 2.0;
+            let frequency = start + rate * centre / 48000.0;
+
+            let peak = peaks(wavelet, 0.5)[0];
+            let estimate = wavelet.bins[peak].frequency;
+            assert!(
+               (estimate - frequency).abs() < 1.0,
+               "{}/{}: frame {} at {} Hz estimated as {} Hz",
+               frame_size,
+               step_size,
+               j,
+               frequency,
+               estimate
+            );
+         }
+      }
+   }
+
+   #[test]
+   fn f32_matches_f64() {
+      for &(frame_size, step_size) in CONFIGS.iter() {
+         let mut freq = Frequencer::new(48000, frame_size, step_size).unwrap();
+         let mut single = Frequencer::<f32>::new(48000, frame_size, step_size).unwrap();
+         for j in 0..frame_size / step_size + 4 {
+            let audio = (0..step_size)
+               .map(|n| {
+                  let t = (j * step_size + n) as f64 / 48000.0;
+                  0.5 * f64::sin(2.0 * PI * 1000.3 * t) + 0.1 * f64::sin(2.0 * PI * 5432.1 * t)
+               })
+               .collect::<Vec<_>>();
+            let expected = freq.feed_audio(&audio);
+            let audio = audio.iter().map(|x| *x as f32).collect::<Vec<_>>();
+            let wavelet = single.feed_audio(&audio);
+
+            let loudest = expected
+               .bins
+               .iter()
+               .map(|bin| bin.amplitude)
+               .fold(0.0, f64::max);
+            for (a, b) in wavelet.bins.iter().zip(expected.bins.iter()) {
+               assert!((a.amplitude as f64 - b.amplitude).abs() < 1e-6 * loudest);
+               // The frequencies of the leakage are only numerical noise
+               if b.amplitude > 1e-2 * loudest {
+                  assert!(
+                     (a.frequency as f64 - b.frequency).abs() < 0.01,
+                     "{}/{}: {} Hz estimated as {} Hz",
+                     frame_size,
+                     step_size,
+                     b.frequency,
+                     a.frequency
+                  );
+               }
+            }
+         }
+      }
    }
 }
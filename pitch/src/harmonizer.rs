@@ -0,0 +1,447 @@
+use alloc::{collections::VecDeque, vec::Vec};
+use core::f64::consts::FRAC_PI_4;
+
+use crate::{
+   cast,
+   detect::{Hps, PitchEstimate},
+   notes::{Interval, Key},
+   Error, FrameContext, Frequencer, FrequencerStream, Resynth, ResynthStream, Sample, ShiftMode,
+   Tuning, Wavelet, Window,
+};
+
+/// How far a [`Voice`] is shifted from the input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VoiceInterval {
+   /// Always the same interval, regardless of the key.
+   Fixed(Interval),
+   /// A number of scale steps in the key of the [`Harmonizer`], e.g. `2` for a
+   /// third above, which is major or minor depending on the sung note.
+   Diatonic(i32),
+}
+
+/// A voice of a [`Harmonizer`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Voice {
+   pub interval: VoiceInterval,
+   /// The linear gain of the voice.
+   pub gain: f64,
+   /// The position in the stereo field, from `-1.0` for left to `1.0` for right.
+   pub pan: f64,
+   /// An additional shift in cents, to make the voice less synthetic.
+   pub detune: f64,
+}
+
+impl Voice {
+   /// A voice at a fixed interval, at full gain in the center.
+   pub fn fixed(interval: Interval) -> Self {
+      Self::new(VoiceInterval::Fixed(interval))
+   }
+
+   /// A voice `steps` degrees of the key away, at full gain in the center.
+   pub fn diatonic(steps: i32) -> Self {
+      Self::new(VoiceInterval::Diatonic(steps))
+   }
+
+   fn new(interval: VoiceInterval) -> Self {
+      Self {
+         interval,
+         gain: 1.0,
+         pan: 0.0,
+         detune: 0.0,
+      }
+   }
+
+   /// The gains of the left and right channel, using a constant power pan law.
+   fn pan_gains(&self) -> (f64, f64) {
+      let angle = (self.pan.clamp(-1.0, 1.0) + 1.0) * FRAC_PI_4;
+      (self.gain * angle.cos(), self.gain * angle.sin())
+   }
+
+   fn is_diatonic(&self) -> bool {
+      matches!(self.interval, VoiceInterval::Diatonic(_))
+   }
+}
+
+struct HarmonyVoice<T> {
+   voice: Voice,
+   resynth: ResynthStream<T>,
+   ratio: f64,
+   delay: usize,
+}
+
+/// Adds pitch shifted voices to the input, and mixes them with the dry signal
+/// into stereo output.
+///
+/// The input is analysed once and every voice is synthesized from the same
+/// wavelets.
+/// Diatonic voices follow the pitch detected in the input, such that they stay
+/// in the key.
+pub struct Harmonizer<T = f64> {
+   freq: FrequencerStream<T>,
+   window: Window,
+   voices: Vec<HarmonyVoice<T>>,
+   key: Option<Key>,
+   tuning: Tuning,
+   detector: Hps,
+   min_confidence: f64,
+   mode: ShiftMode,
+   dry_gain: f64,
+   dry: VecDeque<T>,
+   ctx: FrameContext,
+   headroom: usize,
+   wavelets: Vec<Wavelet<T>>,
+   mix_buf: Vec<T>,
+}
+
+impl<T: Sample> Harmonizer<T> {
+   pub fn new(sample_rate: usize, frame_size: usize, step_size: usize) -> Result<Self, Error> {
+      Self::with_window(sample_rate, frame_size, step_size, Window::Hann)
+   }
+
+   pub fn with_window(
+      sample_rate: usize,
+      frame_size: usize,
+      step_size: usize,
+      window: Window,
+   ) -> Result<Self, Error> {
+      let freq = Frequencer::with_window(sample_rate, frame_size, step_size, window)?;
+
+      // The dry signal is delayed to line up with the voices
+      let latency = frame_size + step_size;
+
+      Ok(Self {
+         freq: FrequencerStream::new(freq),
+         window,
+         voices: vec![],
+         key: None,
+         tuning: Tuning::default(),
+         detector: Hps::new(),
+         min_confidence: 0.5,
+         mode: ShiftMode::Interpolated,
+         dry_gain: 1.0,
+         dry: core::iter::repeat(T::zero()).take(latency).collect(),
+         ctx: FrameContext {
+            sample_rate,
+            frame_size,
+            step_size,
+            frame_index: 0,
+         },
+         // Like in the SpectralProcessor, the voices lag a step behind, such
+         // that there is always enough output to match the input
+         headroom: step_size,
+         wavelets: vec![],
+         mix_buf: vec![],
+      })
+   }
+
+   pub fn sample_rate(&self) -> usize {
+      self.ctx.sample_rate
+   }
+
+   pub fn frame_size(&self) -> usize {
+      self.ctx.frame_size
+   }
+
+   pub fn step_size(&self) -> usize {
+      self.ctx.step_size
+   }
+
+   /// Add a voice, returning its index.
+   ///
+   /// Voices can be added at any time and start with the next frame.
+   pub fn add_voice(&mut self, voice: Voice) -> usize {
+      let resynth = Resynth::with_window(
+         self.ctx.sample_rate,
+         self.ctx.frame_size,
+         self.ctx.step_size,
+         self.window,
+      )
+      .expect("parameters were checked by the frequencer");
+
+      self.voices.push(HarmonyVoice {
+         voice,
+         resynth: ResynthStream::new(resynth),
+         ratio: 1.0,
+         // Stay silent until the voice catches up with the others
+         delay: self.headroom,
+      });
+      self.voices.len() - 1
+   }
+
+   /// Remove the voice at `index`, shifting the indices of the following voices.
+   pub fn remove_voice(&mut self, index: usize) -> Voice {
+      self.voices.remove(index).voice
+   }
+
+   pub fn clear_voices(&mut self) {
+      self.voices.clear();
+   }
+
+   pub fn voices(&self) -> impl Iterator<Item = &Voice> {
+      self.voices.iter().map(|v| &v.voice)
+   }
+
+   /// Change the voice at `index`, e.g. to set its gain or pan.
+   pub fn voice_mut(&mut self, index: usize) -> Option<&mut Voice> {
+      self.voices.get_mut(index).map(|v| &mut v.voice)
+   }
+
+   /// Set the key diatonic voices follow.
+   ///
+   /// Without a key, diatonic voices stay at the pitch of the input.
+   pub fn set_key(&mut self, key: Option<Key>) {
+      self.key = key;
+   }
+
+   pub fn key(&self) -> Option<&Key> {
+      self.key.as_ref()
+   }
+
+   /// Set the tuning, in which the notes of diatonic voices are placed.
+   pub fn set_tuning(&mut self, tuning: Tuning) {
+      self.tuning = tuning;
+   }
+
+   /// Set the confidence below which frames are considered unvoiced.
+   ///
+   /// Diatonic voices keep their last interval during unvoiced frames.
+   pub fn set_min_confidence(&mut self, min_confidence: f64) {
+      self.min_confidence = min_confidence;
+   }
+
+   pub fn set_mode(&mut self, mode: ShiftMode) {
+      self.mode = mode;
+   }
+
+   pub fn detector_mut(&mut self) -> &mut Hps {
+      &mut self.detector
+   }
+
+   /// Set the linear gain of the unprocessed input in the mix.
+   pub fn set_dry_gain(&mut self, dry_gain: f64) {
+      self.dry_gain = dry_gain;
+   }
+
+   pub fn dry_gain(&self) -> f64 {
+      self.dry_gain
+   }
+
+   /// The number of samples the output lags behind the input.
+   pub fn latency(&self) -> usize {
+      self.frame_size() + self.step_size()
+   }
+
+   /// Process `input` into the stereo channels `left` and `right`, which must
+   /// have the same length as `input`.
+   ///
+   /// # Panics
+   /// Panics if the buffers have different lengths.
+   /// Use [`Harmonizer::try_process`] to handle this case instead.
+   pub fn process(&mut self, input: &[T], left: &mut [T], right: &mut [T]) {
+      if let Err(err) = self.try_process(input, left, right) {
+         panic!("{}", err);
+      }
+   }
+
+   /// Like [`Harmonizer::process`] but returns an error instead of panicking.
+   pub fn try_process(
+      &mut self,
+      input: &[T],
+      left: &mut [T],
+      right: &mut [T],
+   ) -> Result<(), Error> {
+      for channel in [&*left, &*right].iter() {
+         if channel.len() != input.len() {
+            return Err(Error::BufferLength {
+               expected: input.len(),
+               actual: channel.len(),
+            });
+         }
+      }
+
+      self.freq.feed_into(input, &mut self.wavelets);
+      self.headroom += self.wavelets.len() * self.ctx.step_size;
+      let min_confidence = self.min_confidence;
+      for wavelet in self.wavelets.drain(..) {
+         let estimate = match &self.key {
+            Some(_) if self.voices.iter().any(|v| v.voice.is_diatonic()) => {
+               self.detector.detect(&wavelet, &self.ctx)
+            }
+            _ => None,
+         };
+         let estimate = estimate.filter(|e| e.is_voiced(min_confidence));
+
+         for voice in self.voices.iter_mut() {
+            if let Some(ratio) = voice_ratio(&voice.voice, &self.key, &self.tuning, estimate) {
+               voice.ratio = ratio;
+            }
+
+            let mut shifted = wavelet.clone();
+            let detune = 2.0f64.powf(voice.voice.detune / 1200.0);
+            shifted.pitch_shift_with(voice.ratio * detune, self.mode);
+            voice.resynth.push_wavelet(shifted);
+         }
+         self.ctx.frame_index += 1;
+      }
+      self.headroom -= input.len();
+
+      // The dry signal in the center
+      let dry_gain = cast::<T>(self.dry_gain * FRAC_PI_4.cos());
+      self.dry.extend(input.iter());
+      for ((l, r), x) in left
+         .iter_mut()
+         .zip(right.iter_mut())
+         .zip(self.dry.drain(..input.len()))
+      {
+         *l = dry_gain * x;
+         *r = dry_gain * x;
+      }
+
+      self.mix_buf.resize(input.len(), T::zero());
+      for voice in self.voices.iter_mut() {
+         // Output silence until the initial delay has passed
+         let silence = usize::min(voice.delay, input.len());
+         self.mix_buf[..silence]
+            .iter_mut()
+            .for_each(|x| *x = T::zero());
+         voice.delay -= silence;
+
+         let written = voice.resynth.try_pull(&mut self.mix_buf[silence..])?;
+         debug_assert_eq!(silence + written, input.len());
+
+         let (left_gain, right_gain) = voice.voice.pan_gains();
+         let (left_gain, right_gain) = (cast::<T>(left_gain), cast::<T>(right_gain));
+         for ((l, r), x) in left
+            .iter_mut()
+            .zip(right.iter_mut())
+            .zip(self.mix_buf.iter())
+         {
+            *l = *l + left_gain * *x;
+            *r = *r + right_gain * *x;
+         }
+      }
+
+      Ok(())
+   }
+}
+
+/// The pitch ratio of `voice` for the current frame, or `None` to keep the last one.
+fn voice_ratio(
+   voice: &Voice,
+   key: &Option<Key>,
+   tuning: &Tuning,
+   estimate: Option<PitchEstimate>,
+) -> Option<f64> {
+   let steps = match voice.interval {
+      VoiceInterval::Fixed(interval) => return Some(interval.ratio()),
+      VoiceInterval::Diatonic(steps) => steps,
+   };
+   let key = match key {
+      Some(key) => key,
+      None => return Some(1.0),
+   };
+
+   // Move the nearest note of the key, and keep the intonation of the input
+   let note = key.nearest(tuning.frequency_to_approx_note(estimate?.frequency))?;
+   let target = key.transpose(&note, steps)?;
+   Some(tuning.frequency(&target) / tuning.frequency(&note))
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::{
+      detect::Yin,
+      notes::{Note, Scale},
+   };
+   use core::f64::consts::PI;
+
+   fn sine(frequency: f64, len: usize) -> Vec<f64> {
+      (0..len)
+         .map(|k| 0.5 * f64::sin(2.0 * PI * frequency * k as f64 / 48000.0))
+         .collect()
+   }
+
+   fn sung(frequency: f64, len: usize) -> Vec<f64> {
+      (0..len)
+         .map(|k| {
+            let t = 2.0 * PI * frequency * k as f64 / 48000.0;
+            (1..6)
+               .map(|h| 0.2 * f64::sin(h as f64 * t) / h as f64)
+               .sum()
+         })
+         .collect()
+   }
+
+   fn run(harmonizer: &mut Harmonizer, input: &[f64], chunk: usize) -> (Vec<f64>, Vec<f64>) {
+      let mut left = vec![0.0; input.len()];
+      let mut right = vec![0.0; input.len()];
+      for ((input, left), right) in input
+         .chunks(chunk)
+         .zip(left.chunks_mut(chunk))
+         .zip(right.chunks_mut(chunk))
+      {
+         harmonizer.process(input, left, right);
+      }
+      (left, right)
+   }
+
+   #[test]
+   fn dry_is_delayed_by_latency() {
+      let input = sine(440.0, 10000);
+      let mut harmonizer = Harmonizer::new(48000, 2048, 512).unwrap();
+      let (left, right) = run(&mut harmonizer, &input, 999);
+
+      let latency = harmonizer.latency();
+      let gain = FRAC_PI_4.cos();
+      for k in 0..input.len() - latency {
+         assert!((left[k + latency] - gain * input[k]).abs() < 1e-12);
+         assert_eq!(left[k], right[k]);
+      }
+   }
+
+   #[test]
+   fn diatonic_third_with_pan() {
+      let c_major = Key::new("C".parse().unwrap(), Scale::major());
+      let mut harmonizer = Harmonizer::new(48000, 4096, 512).unwrap();
+      harmonizer.set_key(Some(c_major));
+      harmonizer.set_dry_gain(0.0);
+
+      let mut voice = Voice::diatonic(2);
+      voice.pan = 1.0;
+      harmonizer.add_voice(voice);
+
+      // A third above E in C major is G
+      let e4 = "E4".parse::<Note>().unwrap().frequency();
+      let (left, right) = run(&mut harmonizer, &sung(e4, 48000), 1000);
+      assert!(left.iter().all(|x| x.abs() < 1e-12));
+
+      let estimate = Yin::new(48000).detect(&right[24000..28096]).unwrap();
+      let g4 = "G4".parse::<Note>().unwrap().frequency();
+      assert!((estimate.frequency - g4).abs() < 5.0, "{:?}", estimate);
+   }
+
+   #[test]
+   fn chunk_size_independent() {
+      let input = sine(300.0, 12000);
+      let output = |chunk| {
+         let mut harmonizer = Harmonizer::new(48000, 1024, 256).unwrap();
+         harmonizer.add_voice(Voice::fixed(Interval::PERFECT_FIFTH));
+         let mut octave = Voice::fixed(Interval::OCTAVE);
+         octave.pan = -0.5;
+         octave.detune = 5.0;
+         harmonizer.add_voice(octave);
+         run(&mut harmonizer, &input, chunk)
+      };
+
+      let (left, right) = output(256);
+      for chunk in [1, 100, 1000].iter() {
+         let (l, r) = output(*chunk);
+         assert!(left.iter().zip(l.iter()).all(|(a, b)| (a - b).abs() < 1e-9));
+         assert!(right
+            .iter()
+            .zip(r.iter())
+            .all(|(a, b)| (a - b).abs() < 1e-9));
+      }
+   }
+}
@@ -1,48 +1,181 @@
-// TODO: #4 Fixed Size Frames using own Types
-// TODO: #9 <- (#5) Custom algs
+//! A phase vocoder for pitch shifting, time stretching and pitch detection.
+//!
+//! The `alloc` feature, which is enabled by default, provides the types that
+//! manage their buffers on the heap and work with any frame size at runtime.
+//! Without it, only the [`fixed`] module with its array backed types is available.
+//! The `std` feature, also enabled by default, adds the [`wav`] module to read,
+//! write and process WAV files.
 
 #![no_std]
 
+#[cfg(feature = "std")]
+extern crate std;
+
+#[cfg(feature = "alloc")]
 #[macro_use]
 extern crate alloc;
+#[cfg(feature = "alloc")]
 use alloc::vec::Vec;
+use core::iter::Sum;
+use num_traits::{float::TotalOrder, Float, FloatConst, FromPrimitive, Signed};
 
+#[cfg(feature = "alloc")]
+mod autotune;
+#[cfg(feature = "alloc")]
+pub use autotune::AutoTune;
+#[cfg(feature = "alloc")]
+pub mod detect;
+#[cfg(feature = "alloc")]
+pub mod effect;
+#[cfg(feature = "alloc")]
+pub use effect::{Chain, FrameContext, SpectralEffect};
+#[cfg(feature = "alloc")]
+pub mod envelope;
+mod error;
+pub use error::Error;
 pub mod fft;
+pub mod fixed;
+mod framing;
+#[cfg(feature = "alloc")]
 mod frequencer;
+#[cfg(feature = "alloc")]
 pub use frequencer::Frequencer;
+#[cfg(feature = "alloc")]
+mod harmonizer;
+#[cfg(feature = "alloc")]
+pub use harmonizer::{Harmonizer, Voice, VoiceInterval};
+#[cfg(feature = "alloc")]
 pub mod resynth;
-pub use resynth::Resynth;
+#[cfg(feature = "alloc")]
+pub use resynth::{PhaseLocking, Resynth};
+#[cfg(feature = "alloc")]
+mod multichannel;
+#[cfg(feature = "alloc")]
+pub use multichannel::{ChannelMode, MultiChannelProcessor, SampleLayout};
+#[cfg(feature = "alloc")]
 pub mod notes;
-pub(crate) mod splat;
+#[cfg(feature = "alloc")]
+mod processor;
+#[cfg(feature = "alloc")]
+pub use processor::{Processor, SpectralProcessor};
+#[cfg(feature = "alloc")]
+mod shifter;
+#[cfg(feature = "alloc")]
+pub use shifter::PitchShifter;
+#[cfg(feature = "alloc")]
+pub mod stream;
+#[cfg(feature = "alloc")]
+pub use stream::{FrequencerStream, ResynthStream};
+#[cfg(feature = "alloc")]
+mod stretch;
+#[cfg(feature = "alloc")]
+pub use stretch::TimeStretcher;
+#[cfg(feature = "alloc")]
+mod tuner;
+#[cfg(feature = "alloc")]
+pub use tuner::{Tuner, TunerReading};
+#[cfg(feature = "alloc")]
+pub mod tuning;
+#[cfg(feature = "alloc")]
+pub use tuning::{Temperament, Tuning};
+#[cfg(feature = "std")]
+pub mod wav;
+pub mod window;
+pub use window::Window;
+
+/// The floating point types the audio can be processed in, `f32` and `f64`.
+///
+/// `f64` is the default everywhere, while `f32` halves the memory bandwidth,
+/// e.g. for WebAssembly or microcontrollers.
+/// The analysis, resynthesis, effects, processors and detectors are generic
+/// over the sample type.
+/// Parameters like ratios, frequencies and gains are always given as `f64`.
+pub trait Sample:
+    fft::FftFloat
+    + Float
+    + FloatConst
+    + FromPrimitive
+    + Signed
+    + TotalOrder
+    + Default
+    + Send
+    + Sync
+    + Sum
+{
+}
+
+impl Sample for f32 {}
+
+impl Sample for f64 {}
+
+/// Convert `x` into the sample type, rounding if necessary.
+pub(crate) fn cast<T: Sample>(x: f64) -> T {
+    fft::FftFloat::from_f64(x)
+}
+
+/// Convert the sample `x` into `f64`, which is exact for both sample types.
+#[cfg(feature = "alloc")]
+pub(crate) fn to_f64<T: Sample>(x: T) -> f64 {
+    num_traits::ToPrimitive::to_f64(&x).unwrap_or(f64::NAN)
+}
 
 #[derive(Debug, Clone)]
-pub struct FrequencyBin {
-    pub amplitude: f64,
-    pub frequency: f64,
+pub struct FrequencyBin<T = f64> {
+    pub amplitude: T,
+    pub frequency: T,
+    /// The phase of the bin at analysis time.
+    pub phase: T,
+}
+
+impl<T: Sample> FrequencyBin<T> {
+    /// A silent bin.
+    pub fn zero() -> Self {
+        FrequencyBin {
+            amplitude: T::zero(),
+            frequency: T::zero(),
+            phase: T::zero(),
+        }
+    }
 }
 
+/// How [`Wavelet::pitch_shift_with`] redistributes the bins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShiftMode {
+    /// Move every bin to the nearest lower bin.
+    /// Cheap, but bins collide when shifting down and leave holes when shifting up.
+    #[default]
+    Fast,
+    /// Distribute the energy of every bin linearly onto the neighbouring bins,
+    /// over `ceil(ratio)` bins on each side when shifting up.
+    /// Preserves the total energy and keeps the frequency of the dominant contributor.
+    Interpolated,
+}
+
+#[cfg(feature = "alloc")]
 #[derive(Debug, Clone)]
-pub struct Wavelet {
+pub struct Wavelet<T = f64> {
     #[allow(dead_code)]
-    pub bins: Vec<FrequencyBin>,
+    pub bins: Vec<FrequencyBin<T>>,
 }
 
-impl Wavelet {
+#[cfg(feature = "alloc")]
+impl<T: Sample> Wavelet<T> {
     pub fn empty(frames: usize) -> Self {
         Wavelet {
             bins: (0..frames)
-                .map(|_| FrequencyBin {
-                    amplitude: 0.0,
-                    frequency: 0.0,
-                })
+                .map(|_| FrequencyBin::zero())
                 .collect::<Vec<_>>(),
         }
     }
 
+    /// The frequency of the loudest bin.
+    ///
+    /// This is often a harmonic rather than the fundamental, see the
+    /// [`detect`] module for proper pitch detection.
     // TODO: Make this fancy with iterators
-    pub fn base_freq(&self) -> f64 {
-        let mut max_freq = 0.0;
-        let mut max_amp = 0.0;
+    pub fn base_freq(&self) -> T {
+        let mut max_freq = T::zero();
+        let mut max_amp = T::zero();
         for bin in &self.bins {
             if bin.amplitude > max_amp {
                 max_amp = bin.amplitude;
@@ -54,27 +187,149 @@ impl Wavelet {
     }
 
     pub fn pitch_shift(&mut self, pitch_shift: f64) {
-        let bins = &self.bins;
-
-        // Create empty bins
-        let mut new_bins = core::iter::repeat(FrequencyBin {
-            amplitude: 0.0,
-            frequency: 0.0,
-        })
-        .take(bins.len())
-        .collect::<Vec<_>>();
-
-        for k in 0..bins.len() {
-            let index = ((k as f64) * pitch_shift) as usize;
-
-            if index < new_bins.len() {
-                new_bins[index].amplitude += bins[k].amplitude;
-                new_bins[index].frequency = bins[k].frequency * pitch_shift
-            }
+        self.pitch_shift_with(pitch_shift, ShiftMode::Fast)
+    }
+
+    pub fn pitch_shift_with(&mut self, pitch_shift: f64, mode: ShiftMode) {
+        match mode {
+            ShiftMode::Fast => self.pitch_shift_fast(pitch_shift),
+            ShiftMode::Interpolated => self.pitch_shift_interpolated(pitch_shift),
         }
+    }
 
+    fn pitch_shift_fast(&mut self, pitch_shift: f64) {
+        let mut new_bins = Wavelet::empty(self.bins.len()).bins;
+        shift_bins_fast(&self.bins, &mut new_bins, pitch_shift);
         self.bins = new_bins;
     }
+
+    fn pitch_shift_interpolated(&mut self, pitch_shift: f64) {
+        let len = self.bins.len();
+        let ratio = cast(pitch_shift);
+
+        // Accumulated energy and strongest contribution of every new bin
+        let mut energy = vec![T::zero(); len];
+        let mut dominant = vec![T::zero(); len];
+        let mut frequency = vec![T::zero(); len];
+        let mut phase = vec![T::zero(); len];
+
+        // Spread every bin with a triangular kernel, at least as wide as the gap
+        // between two shifted bins, so that shifting up leaves no holes
+        let width = pitch_shift.max(1.0);
+        for (k, bin) in self.bins.iter().enumerate() {
+            let position = k as f64 * pitch_shift;
+            let first = (position - width).ceil().max(0.0) as usize;
+            let last = (position + width).floor() as usize;
+            let weight = |index: usize| 1.0 - (index as f64 - position).abs() / width;
+            let total: f64 = (first..=last).map(weight).sum();
+            let bin_energy = bin.amplitude * bin.amplitude;
+
+            for index in first..=last.min(len - 1) {
+                let contribution = bin_energy * cast(weight(index) / total);
+                energy[index] = energy[index] + contribution;
+                if contribution > dominant[index] {
+                    dominant[index] = contribution;
+                    frequency[index] = bin.frequency * ratio;
+                    phase[index] = bin.phase;
+                }
+            }
+        }
+
+        for (k, bin) in self.bins.iter_mut().enumerate() {
+            bin.amplitude = energy[k].sqrt();
+            bin.frequency = frequency[k];
+            bin.phase = phase[k];
+        }
+    }
+}
+
+/// Move every bin of `bins` to the nearest lower bin of the empty `new_bins`.
+fn shift_bins_fast<T: Sample>(
+    bins: &[FrequencyBin<T>],
+    new_bins: &mut [FrequencyBin<T>],
+    pitch_shift: f64,
+) {
+    let ratio = cast(pitch_shift);
+    for (k, bin) in bins.iter().enumerate() {
+        let index = ((k as f64) * pitch_shift) as usize;
+
+        if index < new_bins.len() {
+            new_bins[index].amplitude = new_bins[index].amplitude + bin.amplitude;
+            new_bins[index].frequency = bin.frequency * ratio;
+            new_bins[index].phase = bin.phase;
+        }
+    }
 }
 
 // TODO: Funky functions on Wavelets
+
+#[cfg(test)]
+#[cfg(feature = "alloc")]
+mod tests {
+    use super::*;
+
+    fn energy(wavelet: &Wavelet) -> f64 {
+        wavelet
+            .bins
+            .iter()
+            .map(|bin| bin.amplitude * bin.amplitude)
+            .sum()
+    }
+
+    fn ramp(len: usize) -> Wavelet {
+        let mut wavelet = Wavelet::empty(len);
+        for (k, bin) in wavelet.bins.iter_mut().enumerate() {
+            bin.amplitude = 1.0;
+            bin.frequency = k as f64 * 10.0;
+        }
+        wavelet
+    }
+
+    #[test]
+    fn interpolated_shift_preserves_energy() {
+        let mut wavelet = ramp(256);
+        let before = energy(&wavelet);
+        wavelet.pitch_shift_with(0.7, ShiftMode::Interpolated);
+        assert!((energy(&wavelet) - before).abs() < 1e-9);
+    }
+
+    #[test]
+    fn interpolated_shift_has_no_holes() {
+        let mut fast = ramp(256);
+        fast.pitch_shift_with(1.5, ShiftMode::Fast);
+        assert!(fast.bins[..200].iter().any(|bin| bin.amplitude == 0.0));
+
+        let mut interpolated = ramp(256);
+        interpolated.pitch_shift_with(1.5, ShiftMode::Interpolated);
+        assert!(interpolated.bins[..200]
+            .iter()
+            .all(|bin| bin.amplitude > 0.0));
+
+        // The frequency comes from the closest source bin
+        assert_eq!(interpolated.bins[30].frequency, 300.0);
+    }
+
+    #[test]
+    fn interpolated_shift_up_by_octaves() {
+        for ratio in [2.0, 2.5, 3.0] {
+            let mut fast = ramp(256);
+            fast.pitch_shift_with(ratio, ShiftMode::Fast);
+            assert!(fast.bins[..240].iter().any(|bin| bin.amplitude == 0.0));
+
+            let mut interpolated = ramp(256);
+            interpolated.pitch_shift_with(ratio, ShiftMode::Interpolated);
+            assert!(interpolated.bins[..240]
+                .iter()
+                .all(|bin| bin.amplitude > 0.0));
+
+            // Nothing is shifted past the last bin, so all the energy is kept
+            let mut wavelet = ramp(256);
+            wavelet.bins[80..]
+                .iter_mut()
+                .for_each(|bin| bin.amplitude = 0.0);
+            let before = energy(&wavelet);
+            wavelet.pitch_shift_with(ratio, ShiftMode::Interpolated);
+            assert!((energy(&wavelet) - before).abs() < 1e-9);
+        }
+    }
+}
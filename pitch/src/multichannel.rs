@@ -0,0 +1,508 @@
+use alloc::vec::Vec;
+
+use crate::{
+   cast,
+   effect::{FrameContext, SpectralEffect},
+   framing::Framing,
+   processor::Processor,
+   Error, Frequencer, FrequencerStream, FrequencyBin, Resynth, ResynthStream, Sample, Wavelet,
+   Window,
+};
+
+/// Which signals a [`MultiChannelProcessor`] analyses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelMode {
+   /// Every channel on its own.
+   #[default]
+   Independent,
+   /// The mid `(L + R) / 2` and side `(L - R) / 2` signals of a stereo pair.
+   /// Effects only applied to the mid signal keep the stereo image intact.
+   MidSide,
+}
+
+/// How the samples of several channels are arranged in one buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SampleLayout {
+   /// One frame after another, e.g. `L R L R L R`.
+   #[default]
+   Interleaved,
+   /// One channel after another, e.g. `L L L R R R`.
+   Planar,
+}
+
+/// Runs a [`SpectralEffect`] on every channel of multi-channel audio.
+///
+/// All channels are analysed and resynthesized with the same parameters, and
+/// every channel gets its own copy of the effect.
+/// With coherence enabled, the synthesis phases of all channels advance
+/// together, such that the phase differences between the channels, and with
+/// them the stereo image, survive effects like pitch shifting.
+pub struct MultiChannelProcessor<E, T = f64> {
+   channels: usize,
+   mode: ChannelMode,
+   coherent: bool,
+   framing: Framing,
+   freqs: Vec<FrequencerStream<T>>,
+   resynths: Vec<ResynthStream<T>>,
+   effects: Vec<E>,
+   ctx: FrameContext,
+   delay: usize,
+   wavelets: Vec<Vec<Wavelet<T>>>,
+   reference: Wavelet<T>,
+   reference_phases: Vec<T>,
+   started: bool,
+   planar: Vec<T>,
+}
+
+impl<T: Sample, E: SpectralEffect<T> + Clone> MultiChannelProcessor<E, T> {
+   pub fn new(
+      sample_rate: usize,
+      frame_size: usize,
+      step_size: usize,
+      channels: usize,
+      effect: E,
+   ) -> Result<Self, Error> {
+      Self::with_window(
+         sample_rate,
+         frame_size,
+         step_size,
+         channels,
+         Window::Hann,
+         effect,
+      )
+   }
+
+   pub fn with_window(
+      sample_rate: usize,
+      frame_size: usize,
+      step_size: usize,
+      channels: usize,
+      window: Window,
+      effect: E,
+   ) -> Result<Self, Error> {
+      if channels == 0 {
+         return Err(Error::ChannelCount {
+            expected: 1,
+            actual: 0,
+         });
+      }
+
+      let mut freqs = Vec::with_capacity(channels);
+      let mut resynths = Vec::with_capacity(channels);
+      for _ in 0..channels {
+         let freq = Frequencer::with_window(sample_rate, frame_size, step_size, window)?;
+         let resynth = Resynth::with_window(sample_rate, frame_size, step_size, window)?;
+         freqs.push(FrequencerStream::new(freq));
+         resynths.push(ResynthStream::new(resynth));
+      }
+
+      Ok(Self {
+         channels,
+         mode: ChannelMode::Independent,
+         coherent: false,
+         framing: Framing::new(sample_rate, frame_size, step_size),
+         freqs,
+         resynths,
+         effects: vec![effect; channels],
+         ctx: FrameContext {
+            sample_rate,
+            frame_size,
+            step_size,
+            frame_index: 0,
+         },
+         // Delay the output by a step, like the SpectralProcessor
+         delay: step_size,
+         wavelets: vec![vec![]; channels],
+         reference: Wavelet::empty(frame_size / 2),
+         reference_phases: vec![T::zero(); frame_size / 2],
+         started: false,
+         planar: vec![],
+      })
+   }
+}
+
+impl<T: Sample, E: SpectralEffect<T>> MultiChannelProcessor<E, T> {
+   pub fn sample_rate(&self) -> usize {
+      self.ctx.sample_rate
+   }
+
+   pub fn frame_size(&self) -> usize {
+      self.ctx.frame_size
+   }
+
+   pub fn step_size(&self) -> usize {
+      self.ctx.step_size
+   }
+
+   pub fn channels(&self) -> usize {
+      self.channels
+   }
+
+   pub fn mode(&self) -> ChannelMode {
+      self.mode
+   }
+
+   /// Set the signals to analyse, which should happen before processing any audio.
+   ///
+   /// [`ChannelMode::MidSide`] needs exactly two channels.
+   pub fn set_mode(&mut self, mode: ChannelMode) -> Result<(), Error> {
+      if mode == ChannelMode::MidSide && self.channels != 2 {
+         return Err(Error::ChannelCount {
+            expected: 2,
+            actual: self.channels,
+         });
+      }
+
+      self.mode = mode;
+      Ok(())
+   }
+
+   pub fn coherent(&self) -> bool {
+      self.coherent
+   }
+
+   /// Keep the phase differences between the channels, or let every channel
+   /// accumulate its phases on its own.
+   pub fn set_coherent(&mut self, coherent: bool) {
+      self.coherent = coherent;
+      for resynth in self.resynths.iter_mut() {
+         resynth.resynth_mut().set_wavelet_phases(coherent);
+      }
+   }
+
+   /// The effects of all channels, or of mid and side.
+   pub fn effects(&self) -> &[E] {
+      &self.effects
+   }
+
+   pub fn effects_mut(&mut self) -> &mut [E] {
+      &mut self.effects
+   }
+
+   /// The number of frames, i.e. samples per channel, the output lags behind the input.
+   pub fn latency(&self) -> usize {
+      self.resynths[0].resynth().latency() + self.step_size()
+   }
+
+   /// Process `input` into `output`, which must have the same length and
+   /// contain a whole number of frames.
+   ///
+   /// # Panics
+   /// Panics if the buffers do not fit together.
+   /// Use [`MultiChannelProcessor::try_process`] to handle this case instead.
+   pub fn process(&mut self, input: &[T], output: &mut [T], layout: SampleLayout) {
+      if let Err(err) = self.try_process(input, output, layout) {
+         panic!("{}", err);
+      }
+   }
+
+   /// Like [`MultiChannelProcessor::process`] but returns an error instead of panicking.
+   pub fn try_process(
+      &mut self,
+      input: &[T],
+      output: &mut [T],
+      layout: SampleLayout,
+   ) -> Result<(), Error> {
+      if input.len() != output.len() {
+         return Err(Error::BufferLength {
+            expected: input.len(),
+            actual: output.len(),
+         });
+      }
+      if input.len() % self.channels != 0 {
+         return Err(Error::BufferLength {
+            expected: input.len() - input.len() % self.channels,
+            actual: input.len(),
+         });
+      }
+
+      let frames = input.len() / self.channels;
+      self.deinterleave(input, layout);
+      if self.mode == ChannelMode::MidSide {
+         let (left, right) = self.planar.split_at_mut(frames);
+         let half = cast::<T>(0.5);
+         for (l, r) in left.iter_mut().zip(right.iter_mut()) {
+            let (mid, side) = ((*l + *r) * half, (*l - *r) * half);
+            *l = mid;
+            *r = side;
+         }
+      }
+
+      for (freq, (audio, wavelets)) in self.freqs.iter_mut().zip(
+         self
+            .planar
+            .chunks_exact(frames.max(1))
+            .zip(self.wavelets.iter_mut()),
+      ) {
+         freq.feed_into(audio, wavelets);
+      }
+
+      // All channels are fed the same amount of audio and produce the same number of frames
+      let count = self.wavelets[0].len();
+      for i in 0..count {
+         for (effect, wavelets) in self.effects.iter_mut().zip(self.wavelets.iter_mut()) {
+            effect.process(&mut wavelets[i], &self.ctx);
+         }
+         if self.coherent {
+            self.cohere(i);
+         }
+         self.ctx.frame_index += 1;
+      }
+      for (resynth, wavelets) in self.resynths.iter_mut().zip(self.wavelets.iter_mut()) {
+         for wv in wavelets.drain(..) {
+            resynth.push_wavelet(wv);
+         }
+      }
+
+      // Output silence until the initial delay has passed
+      let silence = usize::min(self.delay, frames);
+      self.delay -= silence;
+      for (resynth, audio) in self
+         .resynths
+         .iter_mut()
+         .zip(self.planar.chunks_exact_mut(frames.max(1)))
+      {
+         audio[..silence].iter_mut().for_each(|x| *x = T::zero());
+         let written = resynth.try_pull(&mut audio[silence..])?;
+         debug_assert_eq!(silence + written, frames);
+      }
+
+      if self.mode == ChannelMode::MidSide {
+         let (mid, side) = self.planar.split_at_mut(frames);
+         for (m, s) in mid.iter_mut().zip(side.iter_mut()) {
+            let (left, right) = (*m + *s, *m - *s);
+            *m = left;
+            *s = right;
+         }
+      }
+      self.interleave(output, layout);
+
+      Ok(())
+   }
+
+   /// Copy `input` into the planar buffer.
+   fn deinterleave(&mut self, input: &[T], layout: SampleLayout) {
+      self.planar.clear();
+      match layout {
+         SampleLayout::Planar => self.planar.extend_from_slice(input),
+         SampleLayout::Interleaved => {
+            for channel in 0..self.channels {
+               self
+                  .planar
+                  .extend(input.iter().skip(channel).step_by(self.channels));
+            }
+         }
+      }
+   }
+
+   /// Copy the planar buffer into `output`.
+   fn interleave(&self, output: &mut [T], layout: SampleLayout) {
+      match layout {
+         SampleLayout::Planar => output.copy_from_slice(&self.planar),
+         SampleLayout::Interleaved => {
+            let frames = output.len() / self.channels;
+            for (channel, audio) in self.planar.chunks_exact(frames.max(1)).enumerate() {
+               for (y, x) in output
+                  .iter_mut()
+                  .skip(channel)
+                  .step_by(self.channels)
+                  .zip(audio.iter())
+               {
+                  *y = *x;
+               }
+            }
+         }
+      }
+   }
+
+   /// Let the phases of the `i`th wavelet of every channel follow a shared reference.
+   ///
+   /// The reference is the loudest channel of every bin, whose synthesis phase
+   /// is accumulated as usual, and every channel keeps its analysis phase
+   /// difference to the reference.
+   fn cohere(&mut self, i: usize) {
+      for (k, reference) in self.reference.bins.iter_mut().enumerate() {
+         let loudest = self
+            .wavelets
+            .iter()
+            .map(|wavelets| &wavelets[i].bins[k])
+            .fold(
+               None,
+               |loudest: Option<&FrequencyBin<T>>, bin| match loudest {
+                  Some(loudest) if loudest.amplitude >= bin.amplitude => Some(loudest),
+                  _ => Some(bin),
+               },
+            );
+         if let Some(loudest) = loudest {
+            *reference = loudest.clone();
+         }
+      }
+
+      if self.started {
+         self
+            .framing
+            .advance(&self.reference.bins, &mut self.reference_phases);
+      } else {
+         self
+            .framing
+            .start(&self.reference.bins, &mut self.reference_phases);
+         self.started = true;
+      }
+
+      for wavelets in self.wavelets.iter_mut() {
+         for ((bin, reference), phase) in wavelets[i]
+            .bins
+            .iter_mut()
+            .zip(self.reference.bins.iter())
+            .zip(self.reference_phases.iter())
+         {
+            bin.phase = *phase + (bin.phase - reference.phase);
+         }
+      }
+   }
+}
+
+impl<T: Sample, E: SpectralEffect<T>> Processor<T> for MultiChannelProcessor<E, T> {
+   fn sample_rate(&self) -> usize {
+      self.ctx.sample_rate
+   }
+
+   fn channels(&self) -> usize {
+      self.channels
+   }
+
+   fn latency(&self) -> usize {
+      MultiChannelProcessor::latency(self)
+   }
+
+   fn try_process(&mut self, input: &[T], output: &mut [T]) -> Result<(), Error> {
+      MultiChannelProcessor::try_process(self, input, output, SampleLayout::Interleaved)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::effect::PitchShift;
+   use core::f64::consts::PI;
+
+   /// A stereo signal with a phase offset between the channels, interleaved.
+   fn stereo(frames: usize, frequency: f64, offset: f64) -> Vec<f64> {
+      (0..frames)
+         .flat_map(|n| {
+            let t = 2.0 * PI * frequency * n as f64 / 48000.0;
+            vec![0.5 * f64::sin(t), 0.3 * f64::sin(t + offset)]
+         })
+         .collect()
+   }
+
+   fn identity(_: &mut Wavelet, _: &FrameContext) {}
+
+   fn channel(audio: &[f64], channel: usize) -> Vec<f64> {
+      audio.iter().skip(channel).step_by(2).copied().collect()
+   }
+
+   #[test]
+   fn identity_in_both_layouts() {
+      let input = stereo(12000, 440.0, 1.0);
+
+      for &mode in [ChannelMode::Independent, ChannelMode::MidSide].iter() {
+         let mut processor = MultiChannelProcessor::new(48000, 1024, 256, 2, identity).unwrap();
+         processor.set_mode(mode).unwrap();
+         let mut output = vec![0.0; input.len()];
+         for (x, y) in input.chunks(1400).zip(output.chunks_mut(1400)) {
+            processor.process(x, y, SampleLayout::Interleaved);
+         }
+
+         let latency = 2 * processor.latency();
+         for (x, y) in input.iter().zip(output[latency..].iter()) {
+            assert!((x - y).abs() < 1e-3, "{:?}: {} != {}", mode, x, y);
+         }
+      }
+
+      // Planar buffers give the same result
+      let planar = [channel(&input, 0), channel(&input, 1)].concat();
+      let mut interleaved = MultiChannelProcessor::new(48000, 1024, 256, 2, identity).unwrap();
+      let mut planar_processor = MultiChannelProcessor::new(48000, 1024, 256, 2, identity).unwrap();
+      let mut expected = vec![0.0; input.len()];
+      interleaved.process(&input, &mut expected, SampleLayout::Interleaved);
+      let mut output = vec![0.0; input.len()];
+      planar_processor.process(&planar, &mut output, SampleLayout::Planar);
+      assert_eq!(
+         [channel(&expected, 0), channel(&expected, 1)].concat(),
+         output
+      );
+   }
+
+   /// The phase of `audio` relative to a sine of `frequency`, at the end of the buffer.
+   fn phase(audio: &[f64], frequency: f64) -> f64 {
+      let tail = &audio[audio.len() - 4800..];
+      let (re, im) = tail
+         .iter()
+         .enumerate()
+         .fold((0.0, 0.0), |(re, im), (n, x)| {
+            let t = 2.0 * PI * frequency * n as f64 / 48000.0;
+            (re + x * f64::cos(t), im - x * f64::sin(t))
+         });
+      f64::atan2(im, re)
+   }
+
+   #[test]
+   fn coherence_keeps_phase_difference() {
+      // The right channel only starts later, such that it accumulates its phases
+      // from a different starting point when shifted on its own
+      let mut input = stereo(48000, 1011.0, 1.0);
+      input[..2 * 12345]
+         .iter_mut()
+         .skip(1)
+         .step_by(2)
+         .for_each(|x| *x = 0.0);
+      let shifted = 1011.0 * 1.26;
+
+      let difference = |coherent: bool| {
+         let mut processor =
+            MultiChannelProcessor::new(48000, 2048, 512, 2, PitchShift::new(1.26).unwrap())
+               .unwrap();
+         processor.set_coherent(coherent);
+         let mut output = vec![0.0; input.len()];
+         processor.process(&input, &mut output, SampleLayout::Interleaved);
+
+         let diff = phase(&channel(&output, 1), shifted) - phase(&channel(&output, 0), shifted);
+         (diff + PI).rem_euclid(2.0 * PI) - PI
+      };
+
+      assert!((difference(true) - 1.0).abs() < 0.1, "{}", difference(true));
+      assert!(
+         (difference(false) - 1.0).abs() > 0.5,
+         "{}",
+         difference(false)
+      );
+   }
+
+   #[test]
+   fn errors() {
+      assert_eq!(
+         MultiChannelProcessor::new(48000, 1024, 256, 0, identity).err(),
+         Some(Error::ChannelCount {
+            expected: 1,
+            actual: 0
+         })
+      );
+
+      let mut processor = MultiChannelProcessor::new(48000, 1024, 256, 3, identity).unwrap();
+      assert_eq!(
+         processor.set_mode(ChannelMode::MidSide),
+         Err(Error::ChannelCount {
+            expected: 2,
+            actual: 3
+         })
+      );
+      assert_eq!(
+         processor.try_process(&[0.0; 10], &mut [0.0; 10], SampleLayout::Interleaved),
+         Err(Error::BufferLength {
+            expected: 9,
+            actual: 10
+         })
+      );
+   }
+}
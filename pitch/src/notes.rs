@@ -1,51 +1,978 @@
+/// The number of semitones from A4 to `frequency`, in equal temperament with A4 at 440Hz.
+///
+/// Use a [`Tuning`](crate::Tuning) for other reference pitches and temperaments.
+/// Returns NaN if `frequency` is not finite and positive.
 pub fn frequency_to_approx_note(frequency: f64) -> f64 {
-   12.0 * f64::log2(frequency / 440.0)
+   Tuning::default().frequency_to_approx_note(frequency)
 }
 
+/// The frequency of the note `note` semitones above A4, in equal temperament with A4 at 440Hz.
+///
+/// Use a [`Tuning`](crate::Tuning) for other reference pitches and temperaments.
+/// Returns NaN if `note` is not finite or out of range.
 pub fn note_to_frequency(note: f64) -> f64 {
-   440.0 * 2.0f64.powf(note / 12.0)
+   Tuning::default().note_to_frequency(note)
 }
 
-#[derive(Clone)]
+use alloc::{string::String, vec::Vec};
+use core::{
+   convert::TryFrom,
+   fmt,
+   ops::{Add, Sub},
+   str::FromStr,
+};
+
+use crate::{Error, Tuning};
+
+/// The MIDI note number of A4.
+const A4: i16 = 69;
+
+/// The letters of the natural notes, starting at C.
+const LETTERS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+
+/// The pitch classes of the natural notes, starting at C.
+const NATURALS: [i16; 7] = [0, 2, 4, 5, 7, 9, 11];
+
+/// A note of the chromatic scale, in scientific pitch notation.
+///
+/// Octaves start at C, such that middle C is C4 and A4 is 440Hz.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Note(i16);
 
 impl Note {
-   pub fn from_approx(note: f64) -> (Self, f64) {
-      let prec_note = note.round() as i16;
-      let deriv = note / prec_note as f64;
+   /// Create a note from its MIDI note number, where 60 is C4.
+   pub fn from_midi(midi: i16) -> Self {
+      Note(midi)
+   }
+
+   /// Create a note from its name, i.e. `0` for C up to `11` for B, and octave.
+   pub fn new(pitch_class: u8, octave: i8) -> Self {
+      Note((octave as i16 + 1) * 12 + (pitch_class % 12) as i16)
+   }
+
+   /// Round an approximate note, as given by [`frequency_to_approx_note`],
+   /// to the nearest note.
+   ///
+   /// Returns the note and the deviation from it in cents,
+   /// or [`Error::InvalidNote`] if `note` is not finite or out of range.
+   pub fn from_approx(note: f64) -> Result<(Self, f64), Error> {
+      let prec_note = note.round();
+      if !(f64::from(i16::MIN)..=f64::from(i16::MAX)).contains(&prec_note) {
+         return Err(Error::InvalidNote);
+      }
+
+      let cents = (note - prec_note) * 100.0;
+      let midi = (prec_note as i16)
+         .checked_add(A4)
+         .ok_or(Error::InvalidNote)?;
+      Ok((Note(midi), cents))
+   }
+
+   /// Find the nearest note to `frequency` and the deviation from it in cents.
+   pub fn from_frequency(frequency: f64) -> Result<(Self, f64), Error> {
+      Self::from_approx(frequency_to_approx_note(frequency))
+   }
+
+   /// The MIDI note number of this note.
+   pub fn midi(&self) -> i16 {
+      self.0
+   }
+
+   /// The number of semitones from A4 to this note.
+   pub fn value(&self) -> i32 {
+      i32::from(self.0) - i32::from(A4)
+   }
+
+   /// The frequency of this note.
+   pub fn frequency(&self) -> f64 {
+      Tuning::default().frequency(self)
+   }
+
+   /// The pitch class, i.e. `0` for C up to `11` for B.
+   pub fn pitch_class(&self) -> u8 {
+      self.0.rem_euclid(12) as u8
+   }
+
+   /// The name of the note, using sharps for the black keys.
+   ///
+   /// Use [`Key::spell`] to name notes according to a key.
+   pub fn name(&self) -> &'static str {
+      match self.pitch_class() {
+         0 => "C",
+         1 => "C#",
+         2 => "D",
+         3 => "D#",
+         4 => "E",
+         5 => "F",
+         6 => "F#",
+         7 => "G",
+         8 => "G#",
+         9 => "A",
+         10 => "A#",
+         11 => "B",
+         _ => unreachable!(),
+      }
+   }
+
+   /// The octave of the note in scientific pitch notation.
+   pub fn octave(&self) -> i16 {
+      self.0.div_euclid(12) - 1
+   }
+
+   /// The note `interval` above this one, or `None` if it is out of range.
+   pub fn checked_add(self, interval: Interval) -> Option<Note> {
+      self.0.checked_add(interval.0).map(Note)
+   }
+
+   /// The note `interval` below this one, or `None` if it is out of range.
+   pub fn checked_sub(self, interval: Interval) -> Option<Note> {
+      self.0.checked_sub(interval.0).map(Note)
+   }
+
+   /// The interval from `other` up to this note, or `None` if it does not fit an [`Interval`].
+   pub fn interval_from(self, other: Note) -> Option<Interval> {
+      self.0.checked_sub(other.0).map(Interval)
+   }
+
+   /// The number of cents from this note to `frequency`.
+   pub fn cents_to(&self, frequency: f64) -> f64 {
+      1200.0 * f64::log2(frequency / self.frequency())
+   }
+}
+
+impl fmt::Display for Note {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "{}{}", self.name(), self.octave())
+   }
+}
+
+impl fmt::Debug for Note {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      fmt::Display::fmt(self, f)
+   }
+}
+
+/// Parses notes like `"A4"`, `"C#3"`, `"Db-1"` or `"F##5"`.
+impl FromStr for Note {
+   type Err = Error;
+
+   fn from_str(s: &str) -> Result<Self, Error> {
+      let (name, rest) = NoteName::parse_prefix(s)?;
+      let octave = rest.parse::<i16>().map_err(|_| Error::InvalidNote)?;
+
+      // The lowest octave only fits partially, so compute the note in a wider type
+      let midi = (i32::from(octave) + 1) * 12 + i32::from(name.offset());
+      i16::try_from(midi)
+         .map(Note)
+         .map_err(|_| Error::InvalidNote)
+   }
+}
+
+/// Panics if the note is out of range, see [`Note::checked_add`].
+impl Add<Interval> for Note {
+   type Output = Note;
+
+   fn add(self, interval: Interval) -> Note {
+      self.checked_add(interval).expect("note out of range")
+   }
+}
+
+/// Panics if the note is out of range, see [`Note::checked_sub`].
+impl Sub<Interval> for Note {
+   type Output = Note;
+
+   fn sub(self, interval: Interval) -> Note {
+      self.checked_sub(interval).expect("note out of range")
+   }
+}
+
+/// The interval from `other` up to `self`.
+///
+/// Panics if the interval is out of range, see [`Note::interval_from`].
+impl Sub for Note {
+   type Output = Interval;
+
+   fn sub(self, other: Note) -> Interval {
+      self.interval_from(other).expect("interval out of range")
+   }
+}
+
+/// The spelled name of a note without octave, like `F#` or `Gb`.
+///
+/// Unlike [`Note::name`], the same pitch class can have several names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NoteName {
+   letter: u8,
+   accidentals: i8,
+}
+
+impl NoteName {
+   /// Create a name from a letter from `'A'` to `'G'` and the number of
+   /// sharps, or flats if negative.
+   pub fn new(letter: char, accidentals: i8) -> Option<Self> {
+      let letter = LETTERS
+         .iter()
+         .position(|l| *l == letter.to_ascii_uppercase())?;
+      Some(Self {
+         letter: letter as u8,
+         accidentals,
+      })
+   }
+
+   /// Name `pitch_class` with the given letter, adding whatever accidentals are needed.
+   fn with_letter(letter: usize, pitch_class: u8) -> Self {
+      let mut accidentals = (pitch_class as i16 - NATURALS[letter % 7]).rem_euclid(12);
+      if accidentals > 6 {
+         accidentals -= 12;
+      }
+      Self {
+         letter: (letter % 7) as u8,
+         accidentals: accidentals as i8,
+      }
+   }
+
+   /// Name `pitch_class` using sharps, or flats if `flats` is set, for the black keys.
+   fn with_preference(pitch_class: u8, flats: bool) -> Self {
+      let pitch_class = pitch_class % 12;
+      let natural = |pc: u8| NATURALS.iter().position(|n| *n == pc as i16);
+      match natural(pitch_class) {
+         Some(letter) => Self::with_letter(letter, pitch_class),
+         None if flats => Self::with_letter(natural(pitch_class + 1).unwrap(), pitch_class),
+         None => Self::with_letter(natural(pitch_class - 1).unwrap(), pitch_class),
+      }
+   }
+
+   /// Parse a name at the start of `s`, returning the rest of the string.
+   fn parse_prefix(s: &str) -> Result<(Self, &str), Error> {
+      let mut chars = s.char_indices().peekable();
+      let letter = chars.next().map(|(_, c)| c).ok_or(Error::InvalidNote)?;
+      let mut name = Self::new(letter, 0).ok_or(Error::InvalidNote)?;
+
+      while let Some((_, c)) = chars.peek() {
+         let accidentals = match c {
+            '#' | '♯' => name.accidentals.checked_add(1),
+            'b' | '♭' => name.accidentals.checked_sub(1),
+            _ => break,
+         };
+         name.accidentals = accidentals.ok_or(Error::InvalidNote)?;
+         chars.next();
+      }
+
+      let rest = chars.peek().map_or("", |(i, _)| &s[*i..]);
+      Ok((name, rest))
+   }
 
-      (Note(prec_note), deriv)
+   /// The letter of the note, from `'A'` to `'G'`.
+   pub fn letter(&self) -> char {
+      LETTERS[self.letter as usize]
    }
 
-   pub fn value(&self) -> i16 {
+   /// The number of sharps, or flats if negative.
+   pub fn accidentals(&self) -> i8 {
+      self.accidentals
+   }
+
+   /// The pitch class, i.e. `0` for C up to `11` for B.
+   pub fn pitch_class(&self) -> u8 {
+      self.offset().rem_euclid(12) as u8
+   }
+
+   /// The semitones from C of the same octave, which may leave the octave like for Cb.
+   fn offset(&self) -> i16 {
+      NATURALS[self.letter as usize] + self.accidentals as i16
+   }
+}
+
+impl fmt::Display for NoteName {
+   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+      write!(f, "{}", self.letter())?;
+      let accidental = if self.accidentals > 0 { '#' } else { 'b' };
+      for _ in 0..self.accidentals.abs() {
+         write!(f, "{}", accidental)?;
+      }
+      Ok(())
+   }
+}
+
+/// Parses names like `"C"`, `"F#"` or `"Bb"`.
+impl FromStr for NoteName {
+   type Err = Error;
+
+   fn from_str(s: &str) -> Result<Self, Error> {
+      match Self::parse_prefix(s)? {
+         (name, "") => Ok(name),
+         _ => Err(Error::InvalidNote),
+      }
+   }
+}
+
+/// The distance between two notes in semitones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Interval(i16);
+
+impl Interval {
+   pub const UNISON: Interval = Interval(0);
+   pub const MINOR_SECOND: Interval = Interval(1);
+   pub const MAJOR_SECOND: Interval = Interval(2);
+   pub const MINOR_THIRD: Interval = Interval(3);
+   pub const MAJOR_THIRD: Interval = Interval(4);
+   pub const PERFECT_FOURTH: Interval = Interval(5);
+   pub const TRITONE: Interval = Interval(6);
+   pub const PERFECT_FIFTH: Interval = Interval(7);
+   pub const MINOR_SIXTH: Interval = Interval(8);
+   pub const MAJOR_SIXTH: Interval = Interval(9);
+   pub const MINOR_SEVENTH: Interval = Interval(10);
+   pub const MAJOR_SEVENTH: Interval = Interval(11);
+   pub const OCTAVE: Interval = Interval(12);
+
+   pub fn from_semitones(semitones: i16) -> Self {
+      Interval(semitones)
+   }
+
+   /// The size of the interval in semitones, negative if it goes down.
+   pub fn semitones(&self) -> i16 {
       self.0
    }
 
-   fn get_name(&self) -> &'static str {
-      match self.0.rem_euclid(12) {
-         0 => "A",
-         1 => "A#",
-         2 => "B",
-         3 => "C",
-         4 => "C#",
-         5 => "D",
-         6 => "D#",
-         7 => "E",
-         8 => "F",
-         9 => "F#",
-         10 => "G",
-         11 => "G#",
-         _ => panic!(),
+   pub fn checked_add(self, other: Interval) -> Option<Interval> {
+      self.0.checked_add(other.0).map(Interval)
+   }
+
+   pub fn checked_sub(self, other: Interval) -> Option<Interval> {
+      self.0.checked_sub(other.0).map(Interval)
+   }
+
+   /// The frequency ratio of the interval in equal temperament.
+   pub fn ratio(&self) -> f64 {
+      let tuning = Tuning::default();
+      tuning.offset_frequency(self.0 as i64) / tuning.reference()
+   }
+
+   /// The short name of the interval within an octave, like `"m3"` or `"P5"`,
+   /// ignoring its direction and any additional octaves.
+   pub fn name(&self) -> &'static str {
+      match self.0.abs() % 12 {
+         0 => "P1",
+         1 => "m2",
+         2 => "M2",
+         3 => "m3",
+         4 => "M3",
+         5 => "P4",
+         6 => "TT",
+         7 => "P5",
+         8 => "m6",
+         9 => "M6",
+         10 => "m7",
+         11 => "M7",
+         _ => unreachable!(),
+      }
+   }
+}
+
+/// Panics if the interval is out of range, see [`Interval::checked_add`].
+impl Add for Interval {
+   type Output = Interval;
+
+   fn add(self, other: Interval) -> Interval {
+      self.checked_add(other).expect("interval out of range")
+   }
+}
+
+/// Panics if the interval is out of range, see [`Interval::checked_sub`].
+impl Sub for Interval {
+   type Output = Interval;
+
+   fn sub(self, other: Interval) -> Interval {
+      self.checked_sub(other).expect("interval out of range")
+   }
+}
+
+/// The seven modes of the major scale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mode {
+   Ionian,
+   Dorian,
+   Phrygian,
+   Lydian,
+   Mixolydian,
+   Aeolian,
+   Locrian,
+}
+
+/// A musical scale, given by the semitone offsets of its degrees from the root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+   offsets: Vec<u8>,
+}
+
+impl Scale {
+   /// Create a scale from semitone offsets from the root within an octave.
+   /// Offsets of 12 or more wrap around into the octave, and the root is
+   /// always part of the scale.
+   pub fn custom(offsets: &[u8]) -> Self {
+      let mut offsets = offsets.iter().map(|x| x % 12).collect::<Vec<_>>();
+      offsets.push(0);
+      offsets.sort_unstable();
+      offsets.dedup();
+      Self { offsets }
+   }
+
+   /// Create a scale from the steps in semitones between its degrees,
+   /// e.g. `[2, 2, 1, 2, 2, 2, 1]` for major.
+   ///
+   /// The last step back to the octave may be left out.
+   pub fn from_steps(steps: &[u8]) -> Self {
+      let offsets = steps
+         .iter()
+         .scan(0, |offset, step| {
+            *offset += step;
+            Some(*offset)
+         })
+         .collect::<Vec<_>>();
+      Self::custom(&offsets)
+   }
+
+   pub fn chromatic() -> Self {
+      Self::custom(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11])
+   }
+
+   pub fn major() -> Self {
+      Self::mode(Mode::Ionian)
+   }
+
+   /// The natural minor scale.
+   pub fn minor() -> Self {
+      Self::mode(Mode::Aeolian)
+   }
+
+   pub fn harmonic_minor() -> Self {
+      Self::custom(&[0, 2, 3, 5, 7, 8, 11])
+   }
+
+   /// The ascending melodic minor scale.
+   pub fn melodic_minor() -> Self {
+      Self::custom(&[0, 2, 3, 5, 7, 9, 11])
+   }
+
+   pub fn major_pentatonic() -> Self {
+      Self::custom(&[0, 2, 4, 7, 9])
+   }
+
+   pub fn minor_pentatonic() -> Self {
+      Self::custom(&[0, 3, 5, 7, 10])
+   }
+
+   /// The minor pentatonic scale with the added flat fifth.
+   pub fn blues() -> Self {
+      Self::custom(&[0, 3, 5, 6, 7, 10])
+   }
+
+   /// A mode of the major scale.
+   pub fn mode(mode: Mode) -> Self {
+      let steps = [2, 2, 1, 2, 2, 2, 1];
+      let rotated = steps
+         .iter()
+         .cycle()
+         .skip(mode as usize)
+         .take(7)
+         .copied()
+         .collect::<Vec<_>>();
+      Self::from_steps(&rotated)
+   }
+
+   pub fn offsets(&self) -> &[u8] {
+      &self.offsets
+   }
+
+   /// The number of notes in an octave of the scale.
+   pub fn len(&self) -> usize {
+      self.offsets.len()
+   }
+
+   /// Scales always contain their root, so this is always `false`.
+   pub fn is_empty(&self) -> bool {
+      self.offsets.is_empty()
+   }
+
+   /// Whether `note` is part of the scale starting at `root`.
+   pub fn contains(&self, root: &Note, note: &Note) -> bool {
+      let offset = (i32::from(note.0) - i32::from(root.0)).rem_euclid(12) as u8;
+      self.offsets.contains(&offset)
+   }
+
+   /// The note `degree` scale steps above `root`, where `0` is the root itself.
+   ///
+   /// Negative degrees go below the root.
+   /// Returns `None` if the note is out of range.
+   pub fn degree(&self, root: &Note, degree: i32) -> Option<Note> {
+      let len = self.offsets.len() as i64;
+      let degree = i64::from(degree);
+      let octave = degree.div_euclid(len);
+      let offset = i64::from(self.offsets[degree.rem_euclid(len) as usize]);
+      i16::try_from(i64::from(root.0) + octave * 12 + offset)
+         .ok()
+         .map(Note)
+   }
+
+   /// The number of scale steps from `root` to `note`,
+   /// or `None` if `note` is not part of the scale.
+   pub fn position(&self, root: &Note, note: &Note) -> Option<i32> {
+      let distance = i32::from(note.0) - i32::from(root.0);
+      let offset = distance.rem_euclid(12) as u8;
+      let index = self.offsets.iter().position(|o| *o == offset)?;
+      Some(distance.div_euclid(12) * self.offsets.len() as i32 + index as i32)
+   }
+
+   /// Find the note of the scale starting at `root`, that is closest to the
+   /// approximate `note`, as given by [`frequency_to_approx_note`].
+   pub fn nearest(&self, root: &Note, note: f64) -> Option<Note> {
+      // Further away than half an octave, no note is in range
+      let min = f64::from(i16::MIN) - f64::from(A4) - 6.0;
+      let max = f64::from(i16::MAX) - f64::from(A4) + 6.0;
+      let center = note.round();
+      if !(min..=max).contains(&center) {
+         return None;
       }
+
+      let center = center as i32;
+      (center - 6..=center + 6)
+         .filter_map(|offset| i16::try_from(offset).ok()?.checked_add(A4).map(Note))
+         .filter(|candidate| self.contains(root, candidate))
+         .min_by(|a, b| {
+            let a = f64::abs(a.value() as f64 - note);
+            let b = f64::abs(b.value() as f64 - note);
+            a.total_cmp(&b)
+         })
+   }
+}
+
+/// A key, i.e. a scale starting at a spelled tonic.
+///
+/// Knowing the key, notes can be named the way they are written in it,
+/// such as F# in D major, but Gb in Eb minor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Key {
+   tonic: NoteName,
+   scale: Scale,
+   flats: bool,
+}
+
+impl Key {
+   pub fn new(tonic: NoteName, scale: Scale) -> Self {
+      // Scales other than seven note ones use the signature of the related major or minor key
+      let signature = if scale.len() == 7 {
+         scale.clone()
+      } else if scale.offsets.contains(&3) && !scale.offsets.contains(&4) {
+         Scale::minor()
+      } else {
+         Scale::major()
+      };
+      let flats = Self::spell_degrees(tonic, &signature).any(|name| name.accidentals < 0);
+
+      Self {
+         tonic,
+         scale,
+         flats: flats || tonic.accidentals < 0,
+      }
+   }
+
+   pub fn major(tonic: NoteName) -> Self {
+      Self::new(tonic, Scale::major())
+   }
+
+   pub fn minor(tonic: NoteName) -> Self {
+      Self::new(tonic, Scale::minor())
+   }
+
+   pub fn tonic(&self) -> NoteName {
+      self.tonic
+   }
+
+   pub fn scale(&self) -> &Scale {
+      &self.scale
+   }
+
+   /// The tonic in the given octave.
+   pub fn root(&self, octave: i8) -> Note {
+      Note::new(self.tonic.pitch_class(), octave)
+   }
+
+   /// Spell the degrees of a seven note scale with consecutive letters.
+   fn spell_degrees(tonic: NoteName, scale: &Scale) -> impl Iterator<Item = NoteName> + '_ {
+      scale.offsets.iter().enumerate().map(move |(k, offset)| {
+         NoteName::with_letter(
+            tonic.letter as usize + k,
+            (tonic.pitch_class() + offset) % 12,
+         )
+      })
+   }
+
+   /// Whether `note` is part of the key.
+   pub fn contains(&self, note: &Note) -> bool {
+      self.scale.contains(&self.root(4), note)
+   }
+
+   /// Find the note of the key, that is closest to the approximate `note`,
+   /// as given by [`frequency_to_approx_note`].
+   pub fn nearest(&self, note: f64) -> Option<Note> {
+      self.scale.nearest(&self.root(4), note)
+   }
+
+   /// Move `note` by `steps` degrees of the key, e.g. `2` for a diatonic third above.
+   ///
+   /// Returns `None` if `note` is not part of the key, or the result is out of range.
+   pub fn transpose(&self, note: &Note, steps: i32) -> Option<Note> {
+      let root = self.root(4);
+      let position = self.scale.position(&root, note)?;
+      self.scale.degree(&root, position.checked_add(steps)?)
+   }
+
+   /// The name of `note` as written in this key.
+   ///
+   /// In seven note scales, every degree gets its own letter.
+   /// Other notes use sharps or flats, depending on the key signature.
+   pub fn spell(&self, note: &Note) -> NoteName {
+      let offset = (note.pitch_class() + 12 - self.tonic.pitch_class()) % 12;
+      match self.scale.offsets.iter().position(|o| *o == offset) {
+         Some(degree) if self.scale.len() == 7 => {
+            NoteName::with_letter(self.tonic.letter as usize + degree, note.pitch_class())
+         }
+         _ => NoteName::with_preference(note.pitch_class(), self.flats),
+      }
+   }
+
+   /// The name of `note` as written in this key, including the octave, like `"Gb4"`.
+   pub fn note_name(&self, note: &Note) -> String {
+      let name = self.spell(note);
+      // The octave follows the letter, e.g. B#3 sounds like C4
+      let octave = (note.0 - name.offset()).div_euclid(12) - 1;
+      format!("{}{}", name, octave)
    }
 
-   fn get_octave(&self) -> i8 {
-      (self.0 as f32 / 12.0).floor() as i8 + 4
+   /// The chord of `size` notes stacked in thirds of the key, on the
+   /// `degree` of the key in the given octave, where `0` is the tonic.
+   ///
+   /// Returns `None` if any note of the chord is out of range.
+   pub fn chord(&self, degree: i32, size: usize, octave: i8) -> Option<Chord> {
+      let root = self.root(octave);
+      let base = self.scale.degree(&root, degree)?;
+      let intervals = (1..size as i64)
+         .map(|k| {
+            let degree = i32::try_from(i64::from(degree) + 2 * k).ok()?;
+            self.scale.degree(&root, degree)?.interval_from(base)
+         })
+         .collect::<Option<_>>()?;
+      Some(Chord::new(base, intervals))
+   }
+
+   /// The triad on the `degree` of the key in the given octave, where `0` is the tonic.
+   pub fn triad(&self, degree: i32, octave: i8) -> Option<Chord> {
+      self.chord(degree, 3, octave)
+   }
+}
+
+/// The common kinds of chords.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChordKind {
+   Major,
+   Minor,
+   Diminished,
+   Augmented,
+   Sus2,
+   Sus4,
+   Major7,
+   Minor7,
+   Dominant7,
+   HalfDiminished7,
+   Diminished7,
+}
+
+impl ChordKind {
+   const ALL: [ChordKind; 11] = [
+      ChordKind::Major,
+      ChordKind::Minor,
+      ChordKind::Diminished,
+      ChordKind::Augmented,
+      ChordKind::Sus2,
+      ChordKind::Sus4,
+      ChordKind::Major7,
+      ChordKind::Minor7,
+      ChordKind::Dominant7,
+      ChordKind::HalfDiminished7,
+      ChordKind::Diminished7,
+   ];
+
+   /// The intervals of the chord above its root.
+   pub fn intervals(&self) -> Vec<Interval> {
+      let semitones: &[i16] = match self {
+         ChordKind::Major => &[4, 7],
+         ChordKind::Minor => &[3, 7],
+         ChordKind::Diminished => &[3, 6],
+         ChordKind::Augmented => &[4, 8],
+         ChordKind::Sus2 => &[2, 7],
+         ChordKind::Sus4 => &[5, 7],
+         ChordKind::Major7 => &[4, 7, 11],
+         ChordKind::Minor7 => &[3, 7, 10],
+         ChordKind::Dominant7 => &[4, 7, 10],
+         ChordKind::HalfDiminished7 => &[3, 6, 10],
+         ChordKind::Diminished7 => &[3, 6, 9],
+      };
+      semitones.iter().map(|s| Interval(*s)).collect()
    }
 }
 
-impl core::fmt::Debug for Note {
-   fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
-      write!(f, "{}{}", self.get_name(), self.get_octave())
+/// A chord, given by its root and the intervals of the other notes above it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chord {
+   root: Note,
+   intervals: Vec<Interval>,
+}
+
+impl Chord {
+   pub fn new(root: Note, intervals: Vec<Interval>) -> Self {
+      Self { root, intervals }
+   }
+
+   pub fn from_kind(root: Note, kind: ChordKind) -> Self {
+      Self::new(root, kind.intervals())
+   }
+
+   pub fn root(&self) -> Note {
+      self.root
+   }
+
+   pub fn intervals(&self) -> &[Interval] {
+      &self.intervals
+   }
+
+   /// The kind of chord, if it is one of the common ones.
+   pub fn kind(&self) -> Option<ChordKind> {
+      ChordKind::ALL
+         .iter()
+         .copied()
+         .find(|kind| kind.intervals() == self.intervals)
+   }
+
+   /// All notes of the chord from the root upwards.
+   pub fn notes(&self) -> Vec<Note> {
+      core::iter::once(self.root)
+         .chain(self.intervals.iter().map(|i| self.root + *i))
+         .collect()
+   }
+
+   /// The notes of the chord in the given inversion, where `1` moves the
+   /// root up an octave, `2` also the next note and so on.
+   pub fn inversion(&self, inversion: usize) -> Vec<Note> {
+      let mut notes = self.notes();
+      let len = notes.len();
+      for k in 0..inversion {
+         notes[k % len] = notes[k % len] + Interval::OCTAVE;
+      }
+      notes.sort_unstable();
+      notes
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use alloc::string::ToString;
+
+   fn note(name: &str) -> Note {
+      name.parse().unwrap()
+   }
+
+   fn key(tonic: &str, scale: Scale) -> Key {
+      Key::new(tonic.parse().unwrap(), scale)
+   }
+
+   #[test]
+   fn names_and_octaves() {
+      let b3: Note = "B3".parse().unwrap();
+      let c4: Note = "C4".parse().unwrap();
+      assert_eq!(b3.octave(), 3);
+      assert_eq!(c4.octave(), 4);
+      assert_eq!(c4.midi(), 60);
+      assert_eq!(b3.to_string(), "B3");
+      assert!(b3 < c4);
+
+      assert_eq!("A4".parse::<Note>().unwrap().value(), 0);
+      assert_eq!("C#4".parse::<Note>(), "Db4".parse::<Note>());
+      assert_eq!("Cb4".parse::<Note>().unwrap(), b3);
+      assert_eq!("a-1".parse::<Note>().unwrap().midi(), 9);
+      assert_eq!(Note::new(1, 4).to_string(), "C#4");
+      assert_eq!(Note::from_midi(21).to_string(), "A0");
+
+      assert!("H4".parse::<Note>().is_err());
+      assert!("C".parse::<Note>().is_err());
+
+      // Out of range
+      assert!("C3000".parse::<Note>().is_err());
+      assert!("C-3000".parse::<Note>().is_err());
+      assert!("C99999".parse::<Note>().is_err());
+      assert_eq!("G2729".parse::<Note>().unwrap().midi(), i16::MAX);
+      assert!("G#2729".parse::<Note>().is_err());
+      for midi in [i16::MIN, i16::MIN + 1, -1, 0, 127, i16::MAX - 1, i16::MAX] {
+         let note = Note::from_midi(midi);
+         assert_eq!(note.to_string().parse::<Note>(), Ok(note));
+      }
+      assert_eq!(Note::from_midi(i16::MAX).to_string(), "G2729");
+      assert_eq!(Note::from_midi(i16::MIN).to_string(), "E-2732");
+      assert_eq!(Note::from_midi(i16::MIN).value(), -32837);
+      let sharps = "C".to_string() + &"#".repeat(200) + "4";
+      assert!(sharps.parse::<Note>().is_err());
+      let flats = "C".to_string() + &"b".repeat(200);
+      assert!(flats.parse::<NoteName>().is_err());
+   }
+
+   #[test]
+   fn cents_deviation() {
+      let (note, cents) = Note::from_frequency(445.0).unwrap();
+      assert_eq!(note.to_string(), "A4");
+      assert!((cents - 19.56).abs() < 0.01);
+
+      let (note, cents) = Note::from_frequency(255.0).unwrap();
+      assert_eq!(note.to_string(), "C4");
+      assert!((cents + 44.41).abs() < 0.01);
+      assert!((note.cents_to(255.0) - cents).abs() < 1e-9);
+
+      assert!(Note::from_frequency(f64::INFINITY).is_err());
+      assert!(Note::from_frequency(0.0).is_err());
+      assert!(Note::from_frequency(f64::NAN).is_err());
+      assert!(Note::from_approx(1e6).is_err());
+   }
+
+   #[test]
+   fn nearest_in_scale() {
+      let c = "C4".parse::<Note>().unwrap();
+      let scale = Scale::major();
+
+      // C# snaps down to C or up to D
+      assert_eq!(scale.nearest(&c, 4.1).unwrap().to_string(), "D5");
+      assert_eq!(scale.nearest(&c, 3.9).unwrap().to_string(), "C5");
+
+      // A# snaps to B
+      assert_eq!(scale.nearest(&c, 1.2).unwrap().to_string(), "B4");
+      assert!(!scale.contains(&c, &"A#4".parse().unwrap()));
+      assert!(scale.contains(&c, &"C3".parse().unwrap()));
+
+      assert_eq!(scale.nearest(&c, f64::NAN), None);
+      assert_eq!(scale.nearest(&c, f64::INFINITY), None);
+      assert_eq!(scale.nearest(&c, 1e9), None);
+      assert_eq!(scale.nearest(&c, 1e10), None);
+      assert_eq!(scale.nearest(&c, -1e10), None);
+
+      // Only notes in range are candidates
+      let top = Note::from_midi(i16::MAX);
+      let chromatic = Scale::chromatic();
+      assert_eq!(
+         chromatic.nearest(&c, f64::from(top.value()) + 3.0),
+         Some(top)
+      );
+      assert_eq!(chromatic.nearest(&c, f64::from(top.value()) + 7.0), None);
+   }
+
+   #[test]
+   fn intervals() {
+      assert_eq!(note("E4") - note("C4"), Interval::MAJOR_THIRD);
+      assert_eq!(note("C4") + Interval::PERFECT_FIFTH, note("G4"));
+      assert_eq!(note("C4") - Interval::OCTAVE, note("C3"));
+      assert_eq!((note("A3") - note("C4")).semitones(), -3);
+      assert_eq!((note("A3") - note("C4")).name(), "m3");
+      assert!((Interval::OCTAVE.ratio() - 2.0).abs() < 1e-12);
+
+      // Out of range
+      let top = Note::from_midi(i16::MAX);
+      assert_eq!(top.checked_add(Interval::OCTAVE), None);
+      assert_eq!(
+         top.checked_sub(Interval::OCTAVE),
+         Some(Note::from_midi(i16::MAX - 12))
+      );
+      assert_eq!(
+         Note::from_midi(i16::MIN).checked_sub(Interval::UNISON),
+         Some(Note::from_midi(i16::MIN))
+      );
+      assert_eq!(top.interval_from(Note::from_midi(-10)), None);
+      assert_eq!(
+         top.interval_from(Note::from_midi(0)),
+         Some(Interval::from_semitones(i16::MAX))
+      );
+      assert_eq!(
+         Interval::from_semitones(i16::MAX).checked_add(Interval::MINOR_SECOND),
+         None
+      );
+   }
+
+   #[test]
+   fn scales_and_modes() {
+      assert_eq!(Scale::from_steps(&[2, 2, 1, 2, 2, 2, 1]), Scale::major());
+      assert_eq!(Scale::minor().offsets(), &[0, 2, 3, 5, 7, 8, 10]);
+      assert_eq!(Scale::mode(Mode::Dorian).offsets(), &[0, 2, 3, 5, 7, 9, 10]);
+      assert_eq!(
+         Scale::mode(Mode::Locrian).offsets(),
+         &[0, 1, 3, 5, 6, 8, 10]
+      );
+      assert_eq!(Scale::blues().len(), 6);
+
+      let c = note("C4");
+      let scale = Scale::major_pentatonic();
+      assert_eq!(scale.degree(&c, 5), Some(note("C5")));
+      assert_eq!(scale.degree(&c, -1), Some(note("A3")));
+      assert_eq!(scale.position(&c, &note("G3")), Some(-2));
+      assert_eq!(scale.position(&c, &note("F4")), None);
+
+      // Out of range
+      assert_eq!(scale.degree(&c, i32::MAX), None);
+      assert_eq!(scale.degree(&c, i32::MIN), None);
+      let (low, high) = (Note::from_midi(-30000), Note::from_midi(30000));
+      let steps = Scale::chromatic().position(&low, &high).unwrap();
+      assert_eq!(steps, 60000);
+      assert_eq!(Scale::chromatic().degree(&low, steps), Some(high));
+   }
+
+   #[test]
+   fn spelling_in_keys() {
+      let name = |key: &Key, n: &str| key.note_name(&note(n));
+
+      assert_eq!(name(&key("D", Scale::major()), "F#4"), "F#4");
+      assert_eq!(name(&key("Eb", Scale::minor()), "F#4"), "Gb4");
+      assert_eq!(name(&key("F", Scale::major()), "A#4"), "Bb4");
+      assert_eq!(name(&key("C#", Scale::major()), "C4"), "B#3");
+      assert_eq!(name(&key("Gb", Scale::major()), "B3"), "Cb4");
+
+      // Notes outside of the key follow the key signature
+      assert_eq!(name(&key("F", Scale::major()), "G#4"), "Ab4");
+      assert_eq!(name(&key("E", Scale::minor_pentatonic()), "A#4"), "A#4");
+      assert_eq!(name(&key("C", Scale::blues()), "F#4"), "Gb4");
+
+      assert_eq!("F##".parse::<NoteName>().unwrap().to_string(), "F##");
+      assert!("F#4".parse::<NoteName>().is_err());
+   }
+
+   #[test]
+   fn chords() {
+      let c_major = key("C", Scale::major());
+      assert_eq!(c_major.triad(0, 4).unwrap().kind(), Some(ChordKind::Major));
+      assert_eq!(c_major.triad(1, 4).unwrap().kind(), Some(ChordKind::Minor));
+      assert_eq!(
+         c_major.triad(6, 4).unwrap().kind(),
+         Some(ChordKind::Diminished)
+      );
+      assert_eq!(
+         c_major.chord(4, 4, 4).unwrap().kind(),
+         Some(ChordKind::Dominant7)
+      );
+
+      let chord = Chord::from_kind(note("A3"), ChordKind::Minor);
+      assert_eq!(chord.notes(), [note("A3"), note("C4"), note("E4")]);
+      assert_eq!(chord.inversion(1), [note("C4"), note("E4"), note("A4")]);
+
+      // A diatonic third above E in C major is G, above F it is A
+      assert_eq!(c_major.transpose(&note("E4"), 2), Some(note("G4")));
+      assert_eq!(c_major.transpose(&note("F4"), 2), Some(note("A4")));
+      assert_eq!(c_major.transpose(&note("F#4"), 2), None);
+      assert_eq!(c_major.transpose(&note("E4"), i32::MAX), None);
+      assert_eq!(c_major.transpose(&note("E4"), i32::MIN), None);
+      assert_eq!(c_major.chord(i32::MAX - 1, 3, 4), None);
    }
 }
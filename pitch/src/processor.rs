@@ -0,0 +1,220 @@
+use alloc::vec::Vec;
+
+use crate::{
+   effect::{FrameContext, SpectralEffect},
+   Error, Frequencer, FrequencerStream, PhaseLocking, Resynth, ResynthStream, Sample, Wavelet,
+   Window,
+};
+
+/// A stream processor, whose output has the same length as its input.
+///
+/// Lets code like [`wav::process`](crate::wav) drive any processor, delaying
+/// the output by the latency.
+pub trait Processor<T: Sample = f64> {
+   fn sample_rate(&self) -> usize;
+
+   /// The number of interleaved channels of the input and output.
+   fn channels(&self) -> usize;
+
+   /// The number of frames, i.e. samples per channel, the output lags behind the input.
+   fn latency(&self) -> usize;
+
+   /// Process the interleaved `input` into `output`, which must have the same length.
+   fn try_process(&mut self, input: &[T], output: &mut [T]) -> Result<(), Error>;
+}
+
+/// Runs a [`SpectralEffect`] between a [`Frequencer`] and a [`Resynth`].
+///
+/// Owns both the analysis and the resynthesis half, which are guaranteed to
+/// run with the same parameters.
+pub struct SpectralProcessor<E, T = f64> {
+   freq: FrequencerStream<T>,
+   resynth: ResynthStream<T>,
+   effect: E,
+   ctx: FrameContext,
+   delay: usize,
+   wavelets: Vec<Wavelet<T>>,
+}
+
+impl<T: Sample, E: SpectralEffect<T>> SpectralProcessor<E, T> {
+   pub fn new(
+      sample_rate: usize,
+      frame_size: usize,
+      step_size: usize,
+      effect: E,
+   ) -> Result<Self, Error> {
+      Self::with_window(sample_rate, frame_size, step_size, Window::Hann, effect)
+   }
+
+   pub fn with_window(
+      sample_rate: usize,
+      frame_size: usize,
+      step_size: usize,
+      window: Window,
+      effect: E,
+   ) -> Result<Self, Error> {
+      let freq = Frequencer::with_window(sample_rate, frame_size, step_size, window)?;
+      let resynth = Resynth::with_window(sample_rate, frame_size, step_size, window)?;
+
+      Ok(Self {
+         freq: FrequencerStream::new(freq),
+         resynth: ResynthStream::new(resynth),
+         effect,
+         ctx: FrameContext {
+            sample_rate,
+            frame_size,
+            step_size,
+            frame_index: 0,
+         },
+         // Delay the output by a step, such that there is always enough output
+         // to match the input, even if the input is not a multiple of the step size
+         delay: step_size,
+         wavelets: vec![],
+      })
+   }
+
+   pub fn sample_rate(&self) -> usize {
+      self.ctx.sample_rate
+   }
+
+   pub fn frame_size(&self) -> usize {
+      self.ctx.frame_size
+   }
+
+   pub fn step_size(&self) -> usize {
+      self.ctx.step_size
+   }
+
+   pub fn phase_locking(&self) -> PhaseLocking {
+      self.resynth.resynth().phase_locking()
+   }
+
+   pub fn set_phase_locking(&mut self, phase_locking: PhaseLocking) {
+      self.resynth.resynth_mut().set_phase_locking(phase_locking);
+   }
+
+   pub fn effect(&self) -> &E {
+      &self.effect
+   }
+
+   pub fn effect_mut(&mut self) -> &mut E {
+      &mut self.effect
+   }
+
+   /// The number of samples the output lags behind the input.
+   ///
+   /// This is the latency of the [`Resynth`], plus the step the output is delayed
+   /// by, to allow for input that is not a multiple of the step size.
+   pub fn latency(&self) -> usize {
+      self.resynth.resynth().latency() + self.step_size()
+   }
+
+   /// Process `input` into `output`, which must have the same length.
+   ///
+   /// # Panics
+   /// Panics if `input` and `output` have different lengths.
+   /// Use [`SpectralProcessor::try_process`] to handle this case instead.
+   pub fn process(&mut self, input: &[T], output: &mut [T]) {
+      if let Err(err) = self.try_process(input, output) {
+         panic!("{}", err);
+      }
+   }
+
+   /// Like [`SpectralProcessor::process`] but returns an error instead of panicking.
+   pub fn try_process(&mut self, input: &[T], output: &mut [T]) -> Result<(), Error> {
+      if input.len() != output.len() {
+         return Err(Error::BufferLength {
+            expected: input.len(),
+            actual: output.len(),
+         });
+      }
+
+      self.freq.feed_into(input, &mut self.wavelets);
+      for mut wv in self.wavelets.drain(..) {
+         self.effect.process(&mut wv, &self.ctx);
+         self.ctx.frame_index += 1;
+         self.resynth.push_wavelet(wv);
+      }
+
+      // Output silence until the initial delay has passed
+      let silence = usize::min(self.delay, output.len());
+      output[..silence].iter_mut().for_each(|x| *x = T::zero());
+      self.delay -= silence;
+
+      let written = self.resynth.try_pull(&mut output[silence..])?;
+      debug_assert_eq!(silence + written, output.len());
+
+      Ok(())
+   }
+}
+
+impl<T: Sample, E: SpectralEffect<T>> Processor<T> for SpectralProcessor<E, T> {
+   fn sample_rate(&self) -> usize {
+      self.ctx.sample_rate
+   }
+
+   fn channels(&self) -> usize {
+      1
+   }
+
+   fn latency(&self) -> usize {
+      SpectralProcessor::latency(self)
+   }
+
+   fn try_process(&mut self, input: &[T], output: &mut [T]) -> Result<(), Error> {
+      SpectralProcessor::try_process(self, input, output)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::effect::Chain;
+
+   #[test]
+   fn effects_see_every_frame() {
+      let mut frames = 0;
+      let chain = Chain::new().with(move |_: &mut Wavelet, ctx: &FrameContext| {
+         assert_eq!(ctx.frame_index, frames);
+         frames += 1;
+      });
+
+      let mut processor = SpectralProcessor::new(48000, 1024, 256, chain).unwrap();
+      let mut output = vec![0.0; 1000];
+      processor.process(&[0.0; 1000], &mut output);
+      assert_eq!(processor.ctx.frame_index, 3);
+   }
+
+   #[test]
+   fn effect_breaking_wavelet_is_an_error() {
+      let truncate = |wavelet: &mut Wavelet, _: &FrameContext| wavelet.bins.truncate(10);
+      let mut processor = SpectralProcessor::new(48000, 1024, 256, truncate).unwrap();
+      let mut output = vec![0.0; 1000];
+      assert_eq!(
+         processor.try_process(&[0.0; 1000], &mut output),
+         Err(Error::WaveletSize {
+            expected: 512,
+            actual: 10
+         })
+      );
+   }
+
+   #[test]
+   fn identity_is_delayed_by_latency() {
+      let mut processor = SpectralProcessor::new(48000, 1024, 256, Chain::new()).unwrap();
+      let input = (0..20000)
+         .map(|n| f64::sin(n as f64 * 0.05) + 0.5 * f64::cos(n as f64 * 0.31))
+         .collect::<Vec<_>>();
+
+      // In chunks that are no multiple of the step size
+      let mut output = vec![0.0; input.len()];
+      for (x, y) in input.chunks(700).zip(output.chunks_mut(700)) {
+         processor.process(x, y);
+      }
+
+      let latency = processor.latency();
+      for (x, y) in input.iter().zip(output[latency..].iter()) {
+         assert!((x - y).abs() < 1e-3, "{} != {}", x, y);
+      }
+   }
+}
@@ -1,45 +1,82 @@
-use alloc::{collections::VecDeque, vec::Vec};
-use core::{f64::consts::PI, iter::FromIterator};
-use num_complex::Complex64;
+use alloc::vec::Vec;
+use num_complex::Complex;
+use num_traits::Zero;
 
-use crate::Wavelet;
+use crate::{
+   cast, error::check_frame, fft::RealIfft, framing::Framing, window::overlap_normalization, Error,
+   Sample, Wavelet, Window,
+};
 
-use alloc::sync::Arc;
-use rustfft::{FFTplanner, FFT};
+/// How the phases of neighbouring bins are tied together during synthesis.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PhaseLocking {
+   /// Every bin accumulates its phase independently.
+   /// This is the classic phase vocoder, which sounds "phasey".
+   #[default]
+   Off,
+   /// Bins around a spectral peak keep their analysis phase relation to the peak.
+   Identity,
+   /// Like [`PhaseLocking::Identity`], but with the phase relation scaled by the factor.
+   Scaled(f64),
+}
 
-pub struct Resynth {
+/// Synthesizes audio step by step from [`Wavelet`]s, in `f64` or `f32` precision.
+pub struct Resynth<T = f64> {
    sample_rate: usize,
    frame_size: usize,
    step_size: usize,
-   freqs_per_bin: f64,
-   phase_diff_per_frame: f64,
-   oversampling_rate: f64,
-   sample_buf: VecDeque<f64>,
-   phase_buf: Vec<f64>,
-   ifft: Arc<dyn FFT<f64>>,
-   last_wavelet: Wavelet,
+   framing: Framing,
+   window: Window,
+   window_table: Vec<T>,
+   normalization: Vec<T>,
+   sample_buf: Vec<T>,
+   phase_buf: Vec<T>,
+   started: bool,
+   wavelet_phases: bool,
+   phase_locking: PhaseLocking,
+   peaks: Vec<usize>,
+   spectrum: Vec<Complex<T>>,
+   frame: Vec<T>,
+   ifft: RealIfft<T>,
+   last_wavelet: Wavelet<T>,
 }
 
-impl Resynth {
-   pub fn new(sample_rate: usize, frame_size: usize, step_size: usize) -> Result<Self, ()> {
-      if !frame_size.is_power_of_two() {
-         return Err(());
-      }
+impl<T: Sample> Resynth<T> {
+   pub fn new(sample_rate: usize, frame_size: usize, step_size: usize) -> Result<Self, Error> {
+      Self::with_window(sample_rate, frame_size, step_size, Window::Hann)
+   }
 
-      if step_size >= frame_size {
-         return Err(());
-      }
+   pub fn with_window(
+      sample_rate: usize,
+      frame_size: usize,
+      step_size: usize,
+      window: Window,
+   ) -> Result<Self, Error> {
+      check_frame(frame_size, step_size)?;
+
+      let table = window.table(frame_size);
+      let normalization = (0..step_size)
+         .map(|position| cast(overlap_normalization(&table, step_size, position)))
+         .collect();
+      let window_table = table.into_iter().map(cast).collect();
 
       Ok(Self {
          sample_rate,
          frame_size,
          step_size,
-         freqs_per_bin: sample_rate as f64 / frame_size as f64,
-         phase_diff_per_frame: 2.0 * PI * step_size as f64 / frame_size as f64,
-         oversampling_rate: frame_size as f64 / step_size as f64,
-         sample_buf: VecDeque::from_iter(core::iter::repeat(0.0).take(frame_size)),
-         phase_buf: vec![0.0; frame_size],
-         ifft: FFTplanner::new(true).plan_fft(frame_size),
+         framing: Framing::new(sample_rate, frame_size, step_size),
+         window,
+         window_table,
+         normalization,
+         sample_buf: vec![T::zero(); frame_size],
+         phase_buf: vec![T::zero(); frame_size / 2],
+         started: false,
+         wavelet_phases: false,
+         phase_locking: PhaseLocking::Off,
+         peaks: vec![],
+         spectrum: vec![Complex::zero(); frame_size / 2 + 1],
+         frame: vec![T::zero(); frame_size],
+         ifft: RealIfft::new(frame_size)?,
          last_wavelet: Wavelet::empty(frame_size / 2),
       })
    }
@@ -48,89 +85,340 @@ impl Resynth {
       self.sample_rate
    }
 
+   pub fn frame_size(&self) -> usize {
+      self.frame_size
+   }
+
    pub fn step_size(&self) -> usize {
       self.step_size
    }
 
-   pub fn pull_audio(&mut self, audio: &mut [f64], wavelet: Option<Wavelet>) {
-      assert!(audio.len() >= self.step_size());
+   /// Synthesize with the phases of the wavelets as they are, instead of
+   /// accumulating them from the frequencies.
+   ///
+   /// This lets a caller decide on the phases, e.g. to keep several channels coherent.
+   pub(crate) fn set_wavelet_phases(&mut self, wavelet_phases: bool) {
+      self.wavelet_phases = wavelet_phases;
+   }
 
-      let wavelet = match wavelet {
-         Some(wv) => wv,
-         None => self.last_wavelet.clone(),
-      };
+   /// The number of samples the output lags behind the input, when resynthesizing
+   /// the wavelets of a [`Frequencer`](crate::Frequencer) with the same parameters.
+   ///
+   /// This is exactly the frame size, as the output of a frame starts right
+   /// after its last sample has been fed.
+   pub fn latency(&self) -> usize {
+      self.frame_size
+   }
 
-      // do the reverse steps
-      let mut frame = wavelet
-         .bins
-         .iter()
-         // calculate back to amplitude and phase
-         .enumerate()
-         .map(|(k, bin)| {
-            // calculate frequency deviation from frequency
-            let mut freq_dev = bin.frequency - k as f64 * self.freqs_per_bin;
-            freq_dev = freq_dev / self.freqs_per_bin;
+   pub fn window(&self) -> Window {
+      self.window
+   }
 
-            // calculate phase difference from frequency deviation
-            let mut phase_diff = 2.0 * PI * freq_dev / self.oversampling_rate;
+   pub fn phase_locking(&self) -> PhaseLocking {
+      self.phase_locking
+   }
 
-            // add possible overlap
-            phase_diff += k as f64 * self.phase_diff_per_frame;
+   pub fn set_phase_locking(&mut self, phase_locking: PhaseLocking) {
+      self.phase_locking = phase_locking;
+   }
+
+   /// Synthesize the next `step_size` samples of audio into `audio` from `wavelet`.
+   ///
+   /// Pass `None` if no wavelet is available, to let the output fade out.
+   ///
+   /// # Panics
+   /// Panics if `audio` is shorter than `step_size` or the wavelet does not
+   /// have `frame_size / 2` bins.
+   /// Use [`Resynth::try_pull_audio`] to handle these cases instead.
+   pub fn pull_audio(&mut self, audio: &mut [T], wavelet: Option<Wavelet<T>>) {
+      if let Err(err) = self.try_pull_audio(audio, wavelet) {
+         panic!("{}", err);
+      }
+   }
 
-            // add phase diff to output phase buffer
-            self.phase_buf[k] += phase_diff;
+   /// Like [`Resynth::pull_audio`] but returns an error instead of panicking.
+   pub fn try_pull_audio(
+      &mut self,
+      audio: &mut [T],
+      wavelet: Option<Wavelet<T>>,
+   ) -> Result<(), Error> {
+      self.try_pull_audio_from(audio, wavelet.as_ref())
+   }
 
-            // return amplitude and phase
-            (bin.amplitude, self.phase_buf[k])
-         })
-         // turn back into complex numbers
-         .map(|(amp, phase)| Complex64::from_polar(amp, phase))
-         // remove the imaginary part
-         //.map(|x| Complex64::new(x.re, 0.0))
-         .collect::<Vec<_>>();
-
-      // reverse fft
-      //let output = rfft(&output).unwrap();
-      // Extend the frame with 0s
-      // FIXME: Maybe instead mirror the values?
-      frame.extend(core::iter::repeat(Complex64::zero()).take(self.frame_size / 2));
-
-      //let mut frame2 = frame.clone();
-      //frame2.extend(frame.iter().rev());
-      //frame2.extend(std::iter::repeat(Complex64::zero()).take(self.frame_size / 2));
-
-      use rustfft::num_traits::Zero;
-      let mut ifft = vec![Complex64::zero(); self.frame_size];
-      self.ifft.process(&mut frame, &mut ifft);
-
-      // apply window and turn into real numbers
-      let frame_size = self.frame_size;
-      let oversampling_rate = self.oversampling_rate;
-      let output = ifft.iter().enumerate().map(|(k, x)| {
-         let window = -0.5 * f64::cos(2.0 * PI * k as f64 / frame_size as f64) + 0.5;
-         2.0 * window * x.re / ((frame_size / 2) as f64 * oversampling_rate)
-         //2.0 * window * x.re / (frame_size / 2) as f64
-         //window * x.re
-      });
-
-      // drain buffer into audio output
-      audio
+   /// Like [`Resynth::pull_audio`], but borrows the wavelet.
+   ///
+   /// This does not allocate, so the wavelet can be reused for the next frame.
+   ///
+   /// # Panics
+   /// Panics if `audio` is shorter than `step_size` or the wavelet does not
+   /// have `frame_size / 2` bins.
+   /// Use [`Resynth::try_pull_audio_from`] to handle these cases instead.
+   pub fn pull_audio_from(&mut self, audio: &mut [T], wavelet: Option<&Wavelet<T>>) {
+      if let Err(err) = self.try_pull_audio_from(audio, wavelet) {
+         panic!("{}", err);
+      }
+   }
+
+   /// Like [`Resynth::pull_audio_from`] but returns an error instead of panicking.
+   pub fn try_pull_audio_from(
+      &mut self,
+      audio: &mut [T],
+      wavelet: Option<&Wavelet<T>>,
+   ) -> Result<(), Error> {
+      match wavelet {
+         Some(wavelet) => self.synthesize(audio, wavelet),
+         None => {
+            // Move the last wavelet out, to be able to borrow it next to self
+            let last = core::mem::replace(&mut self.last_wavelet, Wavelet::empty(0));
+            let result = self.synthesize(audio, &last);
+            self.last_wavelet = last;
+            result
+         }
+      }
+   }
+
+   fn synthesize(&mut self, audio: &mut [T], wavelet: &Wavelet<T>) -> Result<(), Error> {
+      if audio.len() < self.step_size() {
+         return Err(Error::BufferLength {
+            expected: self.step_size(),
+            actual: audio.len(),
+         });
+      }
+
+      if wavelet.bins.len() != self.frame_size / 2 {
+         return Err(Error::WaveletSize {
+            expected: self.frame_size / 2,
+            actual: wavelet.bins.len(),
+         });
+      }
+
+      // do the reverse steps, starting at the phases of the first wavelet
+      if self.started && !self.wavelet_phases {
+         self.framing.advance(&wavelet.bins, &mut self.phase_buf);
+      } else {
+         self.framing.start(&wavelet.bins, &mut self.phase_buf);
+         self.started = true;
+      }
+
+      match self.phase_locking {
+         _ if self.wavelet_phases => (),
+         PhaseLocking::Off => (),
+         PhaseLocking::Identity => self.lock_phases(wavelet, 1.0),
+         PhaseLocking::Scaled(factor) => self.lock_phases(wavelet, factor),
+      }
+
+      // turn back into complex numbers, the Nyquist bin is not part of the wavelet
+      for ((x, bin), phase) in self
+         .spectrum
          .iter_mut()
-         .zip(self.sample_buf.drain(..self.step_size()))
-         .for_each(|(x, y)| *x = y);
+         .zip(wavelet.bins.iter())
+         .zip(self.phase_buf.iter())
+      {
+         *x = Complex::from_polar(bin.amplitude, *phase);
+      }
+      self.spectrum[self.frame_size / 2] = Complex::zero();
 
-      // extend buffer by stepsize elements
-      self
-         .sample_buf
-         .extend(core::iter::repeat(0.0).take(self.step_size));
+      // reverse fft, the upper half of the spectrum is the mirrored conjugate
+      self.ifft.process(&self.spectrum, &mut self.frame)?;
+
+      // drain buffer into audio output, normalizing the overlap of the windows
+      for ((y, x), normalization) in audio
+         .iter_mut()
+         .zip(self.sample_buf.iter())
+         .zip(self.normalization.iter())
+      {
+         *y = *x * *normalization;
+      }
 
-      // accumulate output to buffer
-      self
+      // move the buffer by a step and fill up with zeros
+      self.sample_buf.copy_within(self.step_size.., 0);
+      let start = self.frame_size - self.step_size;
+      self.sample_buf[start..]
+         .iter_mut()
+         .for_each(|x| *x = T::zero());
+
+      // apply window and accumulate output to buffer
+      let scale = cast::<T>(1.0 / self.frame_size as f64);
+      for ((y, x), window) in self
          .sample_buf
          .iter_mut()
-         .zip(output)
-         .for_each(|(x, y)| *x += y);
+         .zip(self.frame.iter())
+         .zip(self.window_table.iter())
+      {
+         *y = *y + *window * *x * scale;
+      }
+
+      Ok(())
+   }
+
+   /// Lock the phases of all bins to the phase of the peak they belong to.
+   ///
+   /// The peaks keep their accumulated phase, while all other bins keep the
+   /// phase relation to their peak, they had at analysis time, scaled by `factor`.
+   fn lock_phases(&mut self, wavelet: &Wavelet<T>, factor: f64) {
+      let bins = &wavelet.bins;
+      let factor = cast::<T>(factor);
+
+      // Find the local maxima of the spectrum
+      self.peaks.clear();
+      for k in 1..bins.len().saturating_sub(1) {
+         if bins[k].amplitude > bins[k - 1].amplitude && bins[k].amplitude >= bins[k + 1].amplitude
+         {
+            self.peaks.push(k);
+         }
+      }
+
+      if self.peaks.is_empty() {
+         return;
+      }
+
+      // Every bin belongs to the peak on its side of the lowest bin between two peaks
+      let mut start = 0;
+      for (i, &peak) in self.peaks.iter().enumerate() {
+         let end = match self.peaks.get(i + 1) {
+            Some(&next) => (peak..next)
+               .min_by(|a, b| bins[*a].amplitude.total_cmp(&bins[*b].amplitude))
+               .unwrap_or(peak),
+            None => bins.len() - 1,
+         };
+
+         let peak_phase = self.phase_buf[peak];
+         for k in start..=end {
+            if k != peak {
+               self.phase_buf[k] = peak_phase + factor * (bins[k].phase - bins[peak].phase);
+            }
+         }
+         start = end + 1;
+      }
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::{Frequencer, FrequencyBin};
+   use core::f64::consts::PI;
+
+   /// Tones on and between bins and a chirp, all well below Nyquist.
+   fn test_signal(len: usize) -> Vec<f64> {
+      (0..len)
+         .map(|n| {
+            let t = n as f64 / 48000.0;
+            0.3 * f64::sin(2.0 * PI * 187.5 * t)
+               + 0.2 * f64::sin(2.0 * PI * 1234.5 * t + 1.0)
+               + 0.1 * f64::sin(2.0 * PI * (3000.0 * t + 2000.0 * t * t))
+         })
+         .collect()
+   }
+
+   /// The signal to noise ratio of `output` in dB.
+   fn snr(input: &[f64], output: &[f64]) -> f64 {
+      let signal = input.iter().map(|x| x * x).sum::<f64>();
+      let noise = input
+         .iter()
+         .zip(output.iter())
+         .map(|(x, y)| (x - y) * (x - y))
+         .sum::<f64>();
+      10.0 * f64::log10(signal / noise)
+   }
+
+   #[test]
+   fn identity_reconstruction() {
+      let windows = [
+         Window::Rectangular,
+         Window::Hann,
+         Window::Hamming,
+         Window::Blackman,
+         Window::BlackmanHarris,
+         Window::Kaiser(8.0),
+         Window::FlatTop,
+      ];
+
+      for &window in windows.iter() {
+         // Including a step size that does not divide the frame
+         for &step_size in [512, 256, 128, 300].iter() {
+            let mut freq = Frequencer::with_window(48000, 1024, step_size, window).unwrap();
+            let mut resynth = Resynth::with_window(48000, 1024, step_size, window).unwrap();
+
+            let input = test_signal(40 * step_size);
+            let mut output = vec![0.0; input.len()];
+            for (x, y) in input.chunks(step_size).zip(output.chunks_mut(step_size)) {
+               resynth.pull_audio_from(y, Some(&freq.feed_audio(x)));
+            }
+
+            let latency = resynth.latency();
+            let snr = snr(&input[..input.len() - latency], &output[latency..]);
+            assert!(snr > 60.0, "{:?} {}: {} dB", window, step_size, snr);
+         }
+      }
+   }
+
+   #[test]
+   fn f32_agrees_with_f64() {
+      let input = test_signal(180 * 256);
+      let mut expected = vec![0.0; input.len()];
+      let mut freq = Frequencer::new(48000, 1024, 256).unwrap();
+      let mut resynth = Resynth::new(48000, 1024, 256).unwrap();
+      for (x, y) in input.chunks(256).zip(expected.chunks_mut(256)) {
+         resynth.pull_audio_from(y, Some(&freq.feed_audio(x)));
+      }
+
+      let input = input.iter().map(|x| *x as f32).collect::<Vec<_>>();
+      let mut output = vec![0.0; input.len()];
+      let mut freq = Frequencer::<f32>::new(48000, 1024, 256).unwrap();
+      let mut resynth = Resynth::<f32>::new(48000, 1024, 256).unwrap();
+      for (x, y) in input.chunks(256).zip(output.chunks_mut(256)) {
+         resynth.pull_audio_from(y, Some(&freq.feed_audio(x)));
+      }
+
+      // Single precision loses some accuracy in the accumulated phases
+      let output = output.iter().map(|x| *x as f64).collect::<Vec<_>>();
+      let snr = snr(&expected, &output);
+      assert!(snr > 60.0, "{} dB", snr);
+   }
+
+   #[test]
+   fn identity_phase_locking() {
+      let mut resynth = Resynth::new(16000, 16, 4).unwrap();
+      resynth.set_phase_locking(PhaseLocking::Identity);
+
+      let amplitudes = [0.0, 1.0, 3.0, 1.0, 0.2, 0.5, 2.0, 0.5];
+      let phases = [0.0, 0.3, 1.2, -0.7, 2.0, 0.1, -1.5, 0.9];
+      let wavelet = Wavelet {
+         bins: amplitudes
+            .iter()
+            .zip(phases.iter())
+            .enumerate()
+            .map(|(k, (amplitude, phase))| FrequencyBin {
+               amplitude: *amplitude,
+               frequency: k as f64 * 1000.0,
+               phase: *phase,
+            })
+            .collect(),
+      };
+
+      let mut audio = [0.0; 4];
+      resynth.pull_audio(&mut audio, Some(wavelet.clone()));
+      resynth.pull_audio(&mut audio, Some(wavelet));
+
+      // Bins keep their phase relation to their peak
+      let relation = |k: usize, peak: usize| resynth.phase_buf[k] - resynth.phase_buf[peak];
+      for (k, peak) in [(0, 2), (1, 2), (3, 2), (4, 2), (5, 6), (7, 6)].iter() {
+         assert!((relation(*k, *peak) - (phases[*k] - phases[*peak])).abs() < 1e-9);
+      }
+   }
+
+   #[test]
+   fn phase_locking_nan_amplitudes() {
+      let mut resynth = Resynth::new(16000, 16, 4).unwrap();
+      resynth.set_phase_locking(PhaseLocking::Identity);
+
+      let mut wavelet = Wavelet::empty(8);
+      for (k, bin) in wavelet.bins.iter_mut().enumerate() {
+         bin.amplitude = [0.0, 1.0, 0.5, f64::NAN, 0.5, 1.0, 0.0, 0.0][k];
+      }
 
-      debug_assert_eq!(self.sample_buf.len(), self.frame_size);
+      let mut audio = [0.0; 4];
+      resynth.pull_audio(&mut audio, Some(wavelet));
    }
 }
@@ -0,0 +1,193 @@
+use crate::{
+   effect::PitchShift, Error, PhaseLocking, Processor, Sample, ShiftMode, SpectralProcessor, Window,
+};
+
+/// Shifts the pitch of a stream of audio, without changing its speed.
+///
+/// This is a [`SpectralProcessor`] running a [`PitchShift`].
+pub struct PitchShifter<T = f64> {
+   inner: SpectralProcessor<PitchShift<T>, T>,
+}
+
+impl<T: Sample> PitchShifter<T> {
+   pub fn new(sample_rate: usize, frame_size: usize, step_size: usize) -> Result<Self, Error> {
+      Self::with_window(sample_rate, frame_size, step_size, Window::Hann)
+   }
+
+   pub fn with_window(
+      sample_rate: usize,
+      frame_size: usize,
+      step_size: usize,
+      window: Window,
+   ) -> Result<Self, Error> {
+      Ok(Self {
+         inner: SpectralProcessor::with_window(
+            sample_rate,
+            frame_size,
+            step_size,
+            window,
+            PitchShift::new(1.0)?,
+         )?,
+      })
+   }
+
+   pub fn sample_rate(&self) -> usize {
+      self.inner.sample_rate()
+   }
+
+   pub fn frame_size(&self) -> usize {
+      self.inner.frame_size()
+   }
+
+   pub fn step_size(&self) -> usize {
+      self.inner.step_size()
+   }
+
+   /// The current pitch shift ratio, where `2.0` shifts up by an octave.
+   pub fn ratio(&self) -> f64 {
+      self.inner.effect().ratio()
+   }
+
+   /// Fails with [`Error::InvalidParameter`] if the ratio is not finite and positive.
+   pub fn set_ratio(&mut self, ratio: f64) -> Result<(), Error> {
+      self.inner.effect_mut().set_ratio(ratio)
+   }
+
+   pub fn mode(&self) -> ShiftMode {
+      self.inner.effect().mode()
+   }
+
+   /// Choose between fast and interpolating bin redistribution.
+   pub fn set_mode(&mut self, mode: ShiftMode) {
+      self.inner.effect_mut().set_mode(mode);
+   }
+
+   pub fn phase_locking(&self) -> PhaseLocking {
+      self.inner.phase_locking()
+   }
+
+   /// Lock the phases around spectral peaks, which reduces the "phasey" sound.
+   pub fn set_phase_locking(&mut self, phase_locking: PhaseLocking) {
+      self.inner.set_phase_locking(phase_locking);
+   }
+
+   /// The formant shift ratio, or `None` if the formants follow the pitch.
+   pub fn formant_ratio(&self) -> Option<f64> {
+      self.inner.effect().formant_ratio()
+   }
+
+   /// Shift the formants independently of the pitch.
+   ///
+   /// `Some(1.0)` keeps the formants in place, which avoids the "chipmunk"
+   /// effect when shifting voices up.
+   /// Fails with [`Error::InvalidParameter`] if the ratio is not finite and positive.
+   pub fn set_formant_ratio(&mut self, formant_ratio: Option<f64>) -> Result<(), Error> {
+      self.inner.effect_mut().set_formant_ratio(formant_ratio)
+   }
+
+   /// The number of samples the output lags behind the input.
+   pub fn latency(&self) -> usize {
+      self.inner.latency()
+   }
+
+   /// Process `input` into `output`, which must have the same length.
+   ///
+   /// # Panics
+   /// Panics if `input` and `output` have different lengths.
+   /// Use [`PitchShifter::try_process`] to handle this case instead.
+   pub fn process(&mut self, input: &[T], output: &mut [T]) {
+      self.inner.process(input, output)
+   }
+
+   /// Like [`PitchShifter::process`] but returns an error instead of panicking.
+   pub fn try_process(&mut self, input: &[T], output: &mut [T]) -> Result<(), Error> {
+      self.inner.try_process(input, output)
+   }
+}
+
+impl<T: Sample> Processor<T> for PitchShifter<T> {
+   fn sample_rate(&self) -> usize {
+      self.inner.sample_rate()
+   }
+
+   fn channels(&self) -> usize {
+      1
+   }
+
+   fn latency(&self) -> usize {
+      PitchShifter::latency(self)
+   }
+
+   fn try_process(&mut self, input: &[T], output: &mut [T]) -> Result<(), Error> {
+      PitchShifter::try_process(self, input, output)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use alloc::vec::Vec;
+   use core::f64::consts::PI;
+
+   #[test]
+   fn chunk_size_independent() {
+      let input = (0..24000)
+         .map(|k| 0.5 * f64::sin(2.0 * PI * 1000.0 * k as f64 / 48000.0))
+         .collect::<Vec<_>>();
+
+      let mut shifter = PitchShifter::new(48000, 2048, 512).unwrap();
+      shifter.set_ratio(1.5).unwrap();
+      let mut expected = vec![0.0; input.len()];
+      shifter.process(&input, &mut expected);
+
+      // The first step is always silent
+      assert!(expected[..512].iter().all(|x| *x == 0.0));
+      assert!(expected[shifter.latency()..].iter().any(|x| *x != 0.0));
+
+      // Process in oddly sized chunks
+      let mut shifter = PitchShifter::new(48000, 2048, 512).unwrap();
+      shifter.set_ratio(1.5).unwrap();
+      let mut output = vec![0.0; input.len()];
+      for (i, o) in input.chunks(300).zip(output.chunks_mut(300)) {
+         shifter.process(i, o);
+      }
+
+      assert_eq!(expected, output);
+   }
+
+   #[test]
+   fn f32_agrees_with_f64() {
+      let input = (0..24000)
+         .map(|k| 0.5 * f64::sin(2.0 * PI * 440.0 * k as f64 / 48000.0))
+         .collect::<Vec<_>>();
+
+      let mut shifter = PitchShifter::new(48000, 2048, 512).unwrap();
+      shifter.set_ratio(1.25).unwrap();
+      shifter.set_formant_ratio(Some(1.0)).unwrap();
+      let mut expected = vec![0.0; input.len()];
+      shifter.process(&input, &mut expected);
+
+      let input = input.iter().map(|x| *x as f32).collect::<Vec<_>>();
+      let mut shifter = PitchShifter::<f32>::new(48000, 2048, 512).unwrap();
+      shifter.set_ratio(1.25).unwrap();
+      shifter.set_formant_ratio(Some(1.0)).unwrap();
+      let mut output = vec![0.0; input.len()];
+      shifter.process(&input, &mut output);
+
+      let signal = expected.iter().map(|x| x * x).sum::<f64>();
+      let noise = expected
+         .iter()
+         .zip(output.iter())
+         .map(|(x, y)| (x - *y as f64) * (x - *y as f64))
+         .sum::<f64>();
+      // The log spectrum of the formant correction loses most of the precision
+      let snr = 10.0 * f64::log10(signal / noise);
+      assert!(snr > 40.0, "{} dB", snr);
+   }
+
+   #[test]
+   fn mismatched_buffers() {
+      let mut shifter = PitchShifter::new(48000, 2048, 512).unwrap();
+      assert!(shifter.try_process(&[0.0; 10], &mut [0.0; 11]).is_err());
+   }
+}
@@ -0,0 +1,187 @@
+//! Streaming wrappers around [`Frequencer`] and [`Resynth`], that accept
+//! and produce audio buffers of arbitrary length.
+
+use alloc::{collections::VecDeque, vec::Vec};
+
+use crate::{Error, Frequencer, Resynth, Sample, Wavelet};
+
+/// Wraps a [`Frequencer`] to accept audio of any length.
+///
+/// Samples are queued internally until a full step is available.
+pub struct FrequencerStream<T = f64> {
+   freq: Frequencer<T>,
+   pending: Vec<T>,
+}
+
+impl<T: Sample> FrequencerStream<T> {
+   pub fn new(freq: Frequencer<T>) -> Self {
+      let pending = Vec::with_capacity(freq.step_size());
+      Self { freq, pending }
+   }
+
+   pub fn frequencer(&self) -> &Frequencer<T> {
+      &self.freq
+   }
+
+   /// The number of samples queued, waiting for a full step.
+   pub fn pending(&self) -> usize {
+      self.pending.len()
+   }
+
+   /// Feed any number of samples and get all wavelets that could be computed.
+   pub fn feed(&mut self, audio: &[T]) -> Vec<Wavelet<T>> {
+      let mut wavelets = vec![];
+      self.feed_into(audio, &mut wavelets);
+      wavelets
+   }
+
+   /// Like [`FrequencerStream::feed`], but appends the wavelets to `wavelets`.
+   pub fn feed_into<E: Extend<Wavelet<T>>>(&mut self, mut audio: &[T], wavelets: &mut E) {
+      let step_size = self.freq.step_size();
+
+      while !audio.is_empty() {
+         let missing = step_size - self.pending.len();
+         let take = usize::min(missing, audio.len());
+         self.pending.extend_from_slice(&audio[..take]);
+         audio = &audio[take..];
+
+         if self.pending.len() == step_size {
+            wavelets.extend(Some(self.freq.feed_audio(&self.pending)));
+            self.pending.clear();
+         }
+      }
+   }
+}
+
+/// Wraps a [`Resynth`] to fill output buffers of any length.
+///
+/// Wavelets are queued with [`ResynthStream::push_wavelet`] and synthesized
+/// on demand.
+pub struct ResynthStream<T = f64> {
+   resynth: Resynth<T>,
+   wavelets: VecDeque<Wavelet<T>>,
+   ready: VecDeque<T>,
+   step_buf: Vec<T>,
+}
+
+impl<T: Sample> ResynthStream<T> {
+   pub fn new(resynth: Resynth<T>) -> Self {
+      let step_size = resynth.step_size();
+      Self {
+         resynth,
+         wavelets: VecDeque::new(),
+         ready: VecDeque::with_capacity(step_size),
+         step_buf: vec![T::zero(); step_size],
+      }
+   }
+
+   pub fn resynth(&self) -> &Resynth<T> {
+      &self.resynth
+   }
+
+   pub fn resynth_mut(&mut self) -> &mut Resynth<T> {
+      &mut self.resynth
+   }
+
+   /// Queue a wavelet for synthesis.
+   pub fn push_wavelet(&mut self, wavelet: Wavelet<T>) {
+      self.wavelets.push_back(wavelet);
+   }
+
+   /// The number of wavelets waiting to be synthesized.
+   pub fn queued(&self) -> usize {
+      self.wavelets.len()
+   }
+
+   /// The number of samples that can be pulled without queueing more wavelets.
+   pub fn available(&self) -> usize {
+      self.ready.len() + self.wavelets.len() * self.resynth.step_size()
+   }
+
+   /// Fill `audio` with synthesized samples.
+   ///
+   /// Returns the number of samples written, which is less than the length of
+   /// `audio` if not enough wavelets are queued.
+   ///
+   /// # Panics
+   /// Panics if a queued wavelet does not fit the [`Resynth`].
+   /// Use [`ResynthStream::try_pull`] to handle this case instead.
+   pub fn pull(&mut self, audio: &mut [T]) -> usize {
+      match self.try_pull(audio) {
+         Ok(written) => written,
+         Err(err) => panic!("{}", err),
+      }
+   }
+
+   /// Like [`ResynthStream::pull`] but returns an error instead of panicking.
+   ///
+   /// The wavelet that failed to synthesize is dropped.
+   pub fn try_pull(&mut self, audio: &mut [T]) -> Result<usize, Error> {
+      let mut written = 0;
+
+      while written < audio.len() {
+         if self.ready.is_empty() {
+            let wavelet = match self.wavelets.pop_front() {
+               Some(wv) => wv,
+               None => break,
+            };
+
+            self
+               .resynth
+               .try_pull_audio_from(&mut self.step_buf, Some(&wavelet))?;
+            self.ready.extend(self.step_buf.iter());
+         }
+
+         let take = usize::min(self.ready.len(), audio.len() - written);
+         audio[written..written + take]
+            .iter_mut()
+            .zip(self.ready.drain(..take))
+            .for_each(|(x, y)| *x = y);
+         written += take;
+      }
+
+      Ok(written)
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn odd_sized_buffers() {
+      let mut freq = FrequencerStream::new(Frequencer::new(48000, 1024, 256).unwrap());
+      let mut resynth = ResynthStream::new(Resynth::new(48000, 1024, 256).unwrap());
+
+      assert_eq!(freq.feed(&[0.0; 100]).len(), 0);
+      assert_eq!(freq.pending(), 100);
+
+      let wavelets = freq.feed(&[0.0; 700]);
+      assert_eq!(wavelets.len(), 3);
+      assert_eq!(freq.pending(), 32);
+
+      wavelets.into_iter().for_each(|wv| resynth.push_wavelet(wv));
+      assert_eq!(resynth.available(), 768);
+
+      let mut out = [1.0; 500];
+      assert_eq!(resynth.pull(&mut out), 500);
+      assert_eq!(resynth.available(), 268);
+
+      let mut out = [1.0; 500];
+      assert_eq!(resynth.pull(&mut out), 268);
+      assert_eq!(resynth.available(), 0);
+   }
+
+   #[test]
+   fn wrong_wavelet_size() {
+      let mut resynth = ResynthStream::new(Resynth::new(48000, 1024, 256).unwrap());
+      resynth.push_wavelet(Wavelet::empty(100));
+      assert_eq!(
+         resynth.try_pull(&mut [0.0; 256]),
+         Err(Error::WaveletSize {
+            expected: 512,
+            actual: 100
+         })
+      );
+   }
+}
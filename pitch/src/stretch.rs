@@ -0,0 +1,255 @@
+use alloc::{collections::VecDeque, vec::Vec};
+
+use crate::{Error, Frequencer, Resynth, Sample, Wavelet, Window};
+
+/// Changes the speed of audio without changing its pitch.
+///
+/// The synthesis hop is fixed to the step size, while the analysis hop is
+/// chosen per frame according to the stretch factor.
+/// This way, the stretch factor can be changed at any time.
+pub struct TimeStretcher<T = f64> {
+   freq: Frequencer<T>,
+   resynth: Resynth<T>,
+   stretch: f64,
+   hop_error: f64,
+   input: Vec<T>,
+   output: VecDeque<T>,
+   step_buf: Vec<T>,
+   wavelet: Wavelet<T>,
+}
+
+impl<T: Sample> TimeStretcher<T> {
+   pub fn new(sample_rate: usize, frame_size: usize, step_size: usize) -> Result<Self, Error> {
+      Self::with_window(sample_rate, frame_size, step_size, Window::Hann)
+   }
+
+   pub fn with_window(
+      sample_rate: usize,
+      frame_size: usize,
+      step_size: usize,
+      window: Window,
+   ) -> Result<Self, Error> {
+      Ok(Self {
+         freq: Frequencer::with_window(sample_rate, frame_size, step_size, window)?,
+         resynth: Resynth::with_window(sample_rate, frame_size, step_size, window)?,
+         stretch: 1.0,
+         hop_error: 0.0,
+         input: vec![],
+         output: VecDeque::new(),
+         step_buf: vec![T::zero(); step_size],
+         wavelet: Wavelet::empty(frame_size / 2),
+      })
+   }
+
+   pub fn sample_rate(&self) -> usize {
+      self.resynth.sample_rate()
+   }
+
+   pub fn frame_size(&self) -> usize {
+      self.resynth.frame_size()
+   }
+
+   pub fn step_size(&self) -> usize {
+      self.resynth.step_size()
+   }
+
+   /// The current stretch factor, where `2.0` plays back at half the speed.
+   pub fn stretch(&self) -> f64 {
+      self.stretch
+   }
+
+   /// Set the stretch factor, which takes effect with the next frame.
+   ///
+   /// The factor is limited, such that the analysis hop stays between one
+   /// sample and the frame size.
+   /// Fails with [`Error::InvalidParameter`] if the factor is not finite and positive.
+   pub fn set_stretch(&mut self, stretch: f64) -> Result<(), Error> {
+      if !stretch.is_finite() || stretch <= 0.0 {
+         return Err(Error::InvalidParameter("stretch factor"));
+      }
+
+      self.stretch = stretch;
+      Ok(())
+   }
+
+   /// Feed any number of samples of input audio.
+   pub fn push(&mut self, audio: &[T]) {
+      self.input.extend_from_slice(audio);
+
+      loop {
+         // Calculate the analysis hop, carrying over the rounding error
+         let max_hop = (self.frame_size() - 1) as f64;
+         let exact = (self.step_size() as f64 / self.stretch + self.hop_error).clamp(1.0, max_hop);
+         let hop = exact.round() as usize;
+
+         if self.input.len() < hop {
+            break;
+         }
+         self.hop_error = exact - hop as f64;
+
+         self
+            .freq
+            .set_step_size(hop)
+            .expect("hop is clamped to valid step sizes");
+         self
+            .freq
+            .feed_audio_into(&self.input[..hop], &mut self.wavelet);
+         self.input.drain(..hop);
+
+         self
+            .resynth
+            .pull_audio_from(&mut self.step_buf, Some(&self.wavelet));
+         self.output.extend(self.step_buf.iter());
+      }
+   }
+
+   /// Push silence, until all input so far is synthesized and has left the
+   /// output buffer of the resynthesis.
+   ///
+   /// Use this at the end of the input.
+   pub fn flush(&mut self) {
+      let frame_size = self.frame_size();
+      let step_size = self.step_size();
+      let hop = (step_size as f64 / self.stretch)
+         .clamp(1.0, (frame_size - 1) as f64)
+         .ceil() as usize;
+
+      // The last sample leaves the analysis frame after `frame_size` more input
+      // samples, and the resynthesis after `frame_size` more output samples
+      let frames =
+         (self.input.len() + frame_size + hop - 1) / hop + (frame_size + step_size - 1) / step_size;
+      let target = self.output.len() + frames * step_size;
+
+      let silence = vec![T::zero(); hop];
+      while self.output.len() < target {
+         self.push(&silence);
+      }
+   }
+
+   /// The number of output samples available.
+   pub fn available(&self) -> usize {
+      self.output.len()
+   }
+
+   /// Fill `audio` with stretched output.
+   ///
+   /// Returns the number of samples written, which is less than the length of
+   /// `audio` if not enough input was pushed.
+   pub fn pull(&mut self, audio: &mut [T]) -> usize {
+      let written = usize::min(audio.len(), self.output.len());
+      audio
+         .iter_mut()
+         .zip(self.output.drain(..written))
+         .for_each(|(x, y)| *x = y);
+      written
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use core::f64::consts::PI;
+
+   fn zero_crossings(audio: &[f64]) -> usize {
+      audio
+         .windows(2)
+         .filter(|x| x[0] < 0.0 && x[1] >= 0.0)
+         .count()
+   }
+
+   #[test]
+   fn stretch_keeps_pitch() {
+      let input = (0..48000)
+         .map(|k| f64::sin(2.0 * PI * 1000.0 * k as f64 / 48000.0))
+         .collect::<Vec<_>>();
+
+      let mut stretcher = TimeStretcher::new(48000, 2048, 256).unwrap();
+      stretcher.set_stretch(2.0).unwrap();
+      for chunk in input.chunks(1000) {
+         stretcher.push(chunk);
+      }
+
+      let mut output = vec![0.0; stretcher.available()];
+      stretcher.pull(&mut output);
+      assert!((output.len() as i64 - 96000).abs() <= 256);
+
+      // Count the frequency in a steady part of the output
+      let steady = &output[8192..88192];
+      let freq = zero_crossings(steady) as f64 * 48000.0 / steady.len() as f64;
+      assert!((freq - 1000.0).abs() < 20.0, "frequency was {}", freq);
+   }
+
+   #[test]
+   fn change_stretch_mid_stream() {
+      let input = (0..48000)
+         .map(|k| f64::sin(2.0 * PI * 1000.0 * k as f64 / 48000.0))
+         .collect::<Vec<_>>();
+      let mut stretcher = TimeStretcher::new(48000, 2048, 256).unwrap();
+
+      // An extreme factor is limited to the largest hop, and must not leave a
+      // rounding error behind
+      let split = 10 * 2047;
+      stretcher.set_stretch(1e-9).unwrap();
+      stretcher.push(&input[..split]);
+      assert_eq!(stretcher.available(), 10 * 256);
+
+      stretcher.set_stretch(0.5).unwrap();
+      stretcher.push(&input[split..]);
+      let added = stretcher.available() - 10 * 256;
+      let expected = (input.len() - split) / 2;
+      assert!(
+         (added as i64 - expected as i64).abs() <= 256,
+         "added {}",
+         added
+      );
+   }
+
+   #[test]
+   fn flush_drains_output() {
+      let input = (0..48000)
+         .map(|k| f64::sin(2.0 * PI * 1000.0 * k as f64 / 48000.0))
+         .collect::<Vec<_>>();
+      let rms =
+         |audio: &[f64]| (audio.iter().map(|x| x * x).sum::<f64>() / audio.len() as f64).sqrt();
+
+      for stretch in [0.5, 1.0, 2.0] {
+         let mut stretcher = TimeStretcher::new(48000, 2048, 256).unwrap();
+         stretcher.set_stretch(stretch).unwrap();
+         stretcher.push(&input);
+         stretcher.flush();
+
+         let mut output = vec![0.0; stretcher.available()];
+         stretcher.pull(&mut output);
+         let expected = input.len() as f64 * stretch;
+         assert!(output.len() as f64 > expected, "stretch {}", stretch);
+         assert!(
+            output.len() as f64 <= expected + 4.0 * 2048.0,
+            "stretch {}",
+            stretch
+         );
+         assert!(
+            rms(&output[output.len() - 256..]) < 1e-6,
+            "stretch {}",
+            stretch
+         );
+
+         // Flushing again only adds silence
+         stretcher.flush();
+         let mut tail = vec![0.0; stretcher.available()];
+         stretcher.pull(&mut tail);
+         assert!(rms(&tail) < 1e-6, "stretch {}", stretch);
+      }
+   }
+
+   #[test]
+   fn invalid_stretch() {
+      let mut stretcher = TimeStretcher::<f64>::new(48000, 2048, 256).unwrap();
+      for stretch in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+         assert_eq!(
+            stretcher.set_stretch(stretch),
+            Err(Error::InvalidParameter("stretch factor"))
+         );
+      }
+      assert_eq!(stretcher.stretch(), 1.0);
+   }
+}
@@ -0,0 +1,344 @@
+use alloc::vec::Vec;
+
+use crate::{
+   detect::{Hps, PitchEstimate, Yin},
+   notes::Note,
+   to_f64, FrameContext, Sample, Tuning, Wavelet,
+};
+
+/// A stable reading of a [`Tuner`], e.g. for a needle display.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TunerReading {
+   /// The note the needle refers to, including its octave.
+   pub note: Note,
+   /// The deviation of the smoothed frequency from `note`.
+   pub cents: f64,
+   /// The smoothed frequency in Hz.
+   pub frequency: f64,
+   /// The confidence of the last pitch detection.
+   pub confidence: f64,
+}
+
+/// A chromatic tuner, that turns jittery pitch estimates into stable readings.
+///
+/// The detected pitch is smoothed over time, silence is gated, and the note only
+/// changes once the pitch moves clearly past the border to the next note.
+pub struct Tuner<T = f64> {
+   sample_rate: usize,
+   yin: Yin<T>,
+   hps: Hps,
+   tuning: Tuning,
+   smoothing: f64,
+   hysteresis: f64,
+   silence_threshold: f64,
+   min_confidence: f64,
+   release: f64,
+   buffer: Vec<T>,
+   smoothed: Option<f64>,
+   jump: Option<f64>,
+   silent_for: f64,
+   reading: Option<TunerReading>,
+}
+
+impl<T: Sample> Tuner<T> {
+   pub fn new(sample_rate: usize) -> Self {
+      Self {
+         sample_rate,
+         yin: Yin::with_range(sample_rate, 60.0, 1500.0).expect("the range is valid"),
+         hps: Hps::with_range(60.0, 1500.0).expect("the range is valid"),
+         tuning: Tuning::default(),
+         smoothing: 0.1,
+         hysteresis: 20.0,
+         silence_threshold: 0.01,
+         min_confidence: 0.5,
+         release: 0.25,
+         buffer: vec![],
+         smoothed: None,
+         jump: None,
+         silent_for: 0.0,
+         reading: None,
+      }
+   }
+
+   /// Set the tuning, whose notes the readings refer to.
+   pub fn set_tuning(&mut self, tuning: Tuning) {
+      self.tuning = tuning;
+   }
+
+   pub fn tuning(&self) -> &Tuning {
+      &self.tuning
+   }
+
+   /// Set the time constant in seconds, over which the pitch is smoothed.
+   ///
+   /// Jumps of more than a semitone are followed immediately, once they are
+   /// confirmed by a second estimate.
+   pub fn set_smoothing(&mut self, smoothing: f64) {
+      self.smoothing = smoothing;
+   }
+
+   /// Set how many cents the pitch has to move past the middle between two notes,
+   /// before the reading changes to the other note.
+   pub fn set_hysteresis(&mut self, hysteresis: f64) {
+      self.hysteresis = hysteresis;
+   }
+
+   /// Set the RMS level below which the input is considered silent.
+   pub fn set_silence_threshold(&mut self, silence_threshold: f64) {
+      self.silence_threshold = silence_threshold;
+   }
+
+   /// Set the confidence below which pitch estimates are ignored.
+   pub fn set_min_confidence(&mut self, min_confidence: f64) {
+      self.min_confidence = min_confidence;
+   }
+
+   /// Set the time in seconds, for which the last reading is held during
+   /// silence or unvoiced input.
+   pub fn set_release(&mut self, release: f64) {
+      self.release = release;
+   }
+
+   pub fn yin_mut(&mut self) -> &mut Yin<T> {
+      &mut self.yin
+   }
+
+   pub fn hps_mut(&mut self) -> &mut Hps {
+      &mut self.hps
+   }
+
+   /// The current reading, or `None` if there is no pitch.
+   pub fn reading(&self) -> Option<TunerReading> {
+      self.reading
+   }
+
+   /// Feed any number of samples of time domain audio, using [`Yin`] to detect the pitch.
+   ///
+   /// Returns the reading after the last complete analysis window.
+   pub fn feed_audio(&mut self, audio: &[T]) -> Option<TunerReading> {
+      self.buffer.extend_from_slice(audio);
+
+      // Analyse overlapping windows, independent of how the audio is chunked
+      let window = self.yin.min_len();
+      let hop = window / 2;
+      let mut start = 0;
+      while self.buffer.len() - start >= window {
+         let frame = &self.buffer[start..start + window];
+         let energy = frame.iter().map(|x| to_f64(*x * *x)).sum::<f64>();
+         let level = f64::sqrt(energy / window as f64);
+         let estimate = if level >= self.silence_threshold {
+            self.yin.detect(frame)
+         } else {
+            None
+         };
+
+         self.update(estimate, level, hop as f64 / self.sample_rate as f64);
+         start += hop;
+      }
+      self.buffer.drain(..start);
+
+      self.reading
+   }
+
+   /// Feed a wavelet, using [`Hps`] to detect the pitch.
+   ///
+   /// The level for the silence gate assumes a Hann window.
+   pub fn feed_wavelet(
+      &mut self,
+      wavelet: &Wavelet<T>,
+      ctx: &FrameContext,
+   ) -> Option<TunerReading> {
+      // Parseval, with the sum of the squared Hann window being 3/8 of the frame size
+      let energy = wavelet
+         .bins
+         .iter()
+         .map(|bin| to_f64(bin.amplitude * bin.amplitude))
+         .sum::<f64>();
+      let n = ctx.frame_size as f64;
+      let level = f64::sqrt(16.0 * energy / (3.0 * n * n));
+
+      let estimate = if level >= self.silence_threshold {
+         self.hps.detect(wavelet, ctx)
+      } else {
+         None
+      };
+
+      self.update(estimate, level, ctx.step_duration());
+      self.reading
+   }
+
+   fn update(&mut self, estimate: Option<PitchEstimate>, level: f64, elapsed: f64) {
+      let estimate = match estimate {
+         Some(estimate)
+            if level >= self.silence_threshold && estimate.is_voiced(self.min_confidence) =>
+         {
+            estimate
+         }
+         _ => {
+            // Hold the last reading for a moment, then let the needle drop
+            self.silent_for += elapsed;
+            if self.silent_for >= self.release {
+               self.smoothed = None;
+               self.jump = None;
+               self.reading = None;
+            }
+            return;
+         }
+      };
+      self.silent_for = 0.0;
+
+      let note = self.tuning.frequency_to_approx_note(estimate.frequency);
+      let smoothed = match self.smoothed {
+         Some(smoothed) if (note - smoothed).abs() <= 1.0 => {
+            self.jump = None;
+            let alpha = if self.smoothing > 0.0 {
+               1.0 - f64::exp(-elapsed / self.smoothing)
+            } else {
+               1.0
+            };
+            smoothed + (note - smoothed) * alpha
+         }
+         // Ignore single outliers, like octave errors or the attack of a note
+         Some(_) => match self.jump.replace(note) {
+            Some(jump) if (note - jump).abs() <= 1.0 => {
+               self.jump = None;
+               note
+            }
+            _ => return,
+         },
+         None => note,
+      };
+      self.smoothed = Some(smoothed);
+
+      // Only change the note, once the pitch is clearly closer to another one
+      let nearest = match Note::from_approx(smoothed) {
+         Ok((nearest, _)) => nearest,
+         Err(_) => return,
+      };
+      let note = match self.reading {
+         Some(reading)
+            if (smoothed - reading.note.value() as f64).abs() <= 0.5 + self.hysteresis / 100.0 =>
+         {
+            reading.note
+         }
+         _ => nearest,
+      };
+
+      let frequency = self.tuning.note_to_frequency(smoothed);
+      self.reading = Some(TunerReading {
+         note,
+         cents: 1200.0 * f64::log2(frequency / self.tuning.frequency(&note)),
+         frequency,
+         confidence: estimate.confidence,
+      });
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use core::f64::consts::PI;
+
+   fn tone(cents_from_a4: f64, len: usize) -> Vec<f64> {
+      let frequency = 440.0 * 2.0f64.powf(cents_from_a4 / 1200.0);
+      (0..len)
+         .map(|k| {
+            let t = 2.0 * PI * frequency * k as f64 / 48000.0;
+            0.3 * f64::sin(t) + 0.2 * f64::sin(2.0 * t)
+         })
+         .collect()
+   }
+
+   fn note(name: &str) -> Note {
+      name.parse().unwrap()
+   }
+
+   #[test]
+   fn steady_tone() {
+      let mut tuner = Tuner::new(48000);
+      let reading = tuner.feed_audio(&tone(-10.0, 24000)).unwrap();
+      assert_eq!(reading.note, note("A4"));
+      assert!((reading.cents + 10.0).abs() < 1.0, "{:?}", reading);
+      assert!(reading.confidence > 0.9);
+
+      // The readings do not depend on how the audio is chunked
+      let mut chunked = Tuner::new(48000);
+      for chunk in tone(-10.0, 24000).chunks(333) {
+         chunked.feed_audio(chunk);
+      }
+      assert_eq!(chunked.reading(), Some(reading));
+   }
+
+   #[test]
+   fn hysteresis() {
+      let mut tuner = Tuner::new(48000);
+      tuner.set_smoothing(0.0);
+
+      tuner.feed_audio(&tone(40.0, 12000));
+      assert_eq!(tuner.reading().unwrap().note, note("A4"));
+
+      // Slightly past the middle to A#, the reading stays at A
+      let reading = tuner.feed_audio(&tone(60.0, 12000)).unwrap();
+      assert_eq!(reading.note, note("A4"));
+      assert!((reading.cents - 60.0).abs() < 1.0, "{:?}", reading);
+
+      // Clearly closer to A#
+      let reading = tuner.feed_audio(&tone(80.0, 12000)).unwrap();
+      assert_eq!(reading.note, note("A#4"));
+      assert!((reading.cents + 20.0).abs() < 1.0, "{:?}", reading);
+   }
+
+   #[test]
+   fn smoothing_and_silence() {
+      let mut tuner = Tuner::new(48000);
+      tuner.set_smoothing(0.2);
+
+      // Alternate between 15 cents flat and sharp
+      let mut audio = vec![];
+      for k in 0..20 {
+         let cents = if k % 2 == 0 { -15.0 } else { 15.0 };
+         audio.extend(tone(cents, 2400));
+      }
+      let reading = tuner.feed_audio(&audio).unwrap();
+      assert!(reading.cents.abs() < 10.0, "{:?}", reading);
+
+      // A short gap holds the reading, a longer one drops it
+      assert!(tuner.feed_audio(&[0.0; 4800]).is_some());
+      assert!(tuner.feed_audio(&[0.0; 9600]).is_none());
+   }
+
+   #[test]
+   fn wavelets() {
+      let ctx = FrameContext {
+         sample_rate: 48000,
+         frame_size: 4096,
+         step_size: 1024,
+         frame_index: 0,
+      };
+
+      // Harmonics leaking into the neighbouring bins, like after windowing
+      let frequency = 440.0 * 2.0f64.powf(5.0 / 1200.0);
+      let mut wavelet = Wavelet::empty(ctx.frame_size / 2);
+      for (k, bin) in wavelet.bins.iter_mut().enumerate() {
+         for h in 1..4 {
+            let freq = h as f64 * frequency;
+            let distance = k as f64 - freq / ctx.freqs_per_bin();
+            let amplitude = 100.0 * f64::exp(-distance * distance) / h as f64;
+            if amplitude > bin.amplitude {
+               bin.amplitude = amplitude;
+               bin.frequency = freq;
+            }
+         }
+      }
+
+      let mut tuner = Tuner::new(48000);
+      let reading = tuner.feed_wavelet(&wavelet, &ctx).unwrap();
+      assert_eq!(reading.note, note("A4"));
+      assert!((reading.cents - 5.0).abs() < 0.1, "{:?}", reading);
+
+      assert_eq!(
+         tuner.feed_wavelet(&Wavelet::empty(2048), &ctx),
+         Some(reading)
+      );
+   }
+}
@@ -0,0 +1,427 @@
+//! Tunings, that map notes to frequencies.
+//!
+//! The free functions in [`notes`](crate::notes) use twelve tone equal
+//! temperament with A4 at 440Hz.
+//! A [`Tuning`] allows for a different reference pitch and other temperaments.
+
+use alloc::vec::Vec;
+
+use crate::{notes::Note, Error};
+
+/// How the octave is divided into notes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Temperament {
+   /// Twelve tone equal temperament.
+   Equal,
+   /// Five limit just intonation.
+   JustIntonation,
+   /// Pythagorean tuning, built from pure fifths.
+   Pythagorean,
+   /// Quarter comma meantone, built from fifths that give pure major thirds.
+   Meantone,
+   /// Arbitrary degrees, given in cents from the root.
+   ///
+   /// The last degree is the period, usually `1200.0` for the octave.
+   /// The root itself is not part of the list, like in Scala files.
+   /// The degrees must be strictly increasing and above the root.
+   Custom(Vec<f64>),
+}
+
+impl Temperament {
+   /// Parse the contents of a Scala `.scl` file.
+   pub fn from_scala(scl: &str) -> Result<Self, Error> {
+      let mut lines = scl
+         .lines()
+         .map(|line| line.trim())
+         .filter(|line| !line.starts_with('!'));
+
+      // The first line is the description, which may be empty
+      lines.next().ok_or(Error::InvalidScala)?;
+
+      let count = lines
+         .next()
+         .and_then(|line| line.split_whitespace().next())
+         .and_then(|count| count.parse::<usize>().ok())
+         .ok_or(Error::InvalidScala)?;
+
+      let degrees = lines
+         .filter(|line| !line.is_empty())
+         .take(count)
+         .map(|line| parse_scala_pitch(line.split_whitespace().next().unwrap_or("")))
+         .collect::<Result<Vec<_>, _>>()?;
+
+      if degrees.len() != count {
+         return Err(Error::InvalidScala);
+      }
+
+      let temperament = Temperament::Custom(degrees);
+      if !temperament.is_valid() {
+         return Err(Error::InvalidScala);
+      }
+
+      Ok(temperament)
+   }
+
+   /// Whether the degrees are strictly increasing, starting above the root.
+   fn is_valid(&self) -> bool {
+      match self {
+         Temperament::Custom(degrees) => {
+            !degrees.is_empty()
+               && degrees.iter().all(|degree| degree.is_finite())
+               && degrees[0] > 0.0
+               && degrees.windows(2).all(|pair| pair[0] < pair[1])
+         }
+         _ => true,
+      }
+   }
+
+   /// The degrees in cents, starting with the root at `0.0` and ending with the period.
+   fn degrees(&self) -> Vec<f64> {
+      const JUST: [(f64, f64); 12] = [
+         (1.0, 1.0),
+         (16.0, 15.0),
+         (9.0, 8.0),
+         (6.0, 5.0),
+         (5.0, 4.0),
+         (4.0, 3.0),
+         (45.0, 32.0),
+         (3.0, 2.0),
+         (8.0, 5.0),
+         (5.0, 3.0),
+         (9.0, 5.0),
+         (15.0, 8.0),
+      ];
+
+      let mut degrees = match self {
+         Temperament::Equal => (0..12).map(|k| k as f64 * 100.0).collect(),
+         Temperament::JustIntonation => JUST.iter().map(|(a, b)| ratio_to_cents(a / b)).collect(),
+         Temperament::Pythagorean => chain_of_fifths(ratio_to_cents(1.5), -5),
+         Temperament::Meantone => chain_of_fifths(ratio_to_cents(f64::powf(5.0, 0.25)), -3),
+         Temperament::Custom(degrees) => {
+            let mut all = vec![0.0];
+            all.extend_from_slice(degrees);
+            return all;
+         }
+      };
+      degrees.push(1200.0);
+      degrees
+   }
+}
+
+/// Maps notes to frequencies, given a reference pitch and a temperament.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+   reference: f64,
+   root: u8,
+   temperament: Temperament,
+   degrees: Vec<f64>,
+}
+
+impl Default for Tuning {
+   /// Equal temperament with A4 at 440Hz.
+   fn default() -> Self {
+      Self {
+         reference: 440.0,
+         root: 0,
+         temperament: Temperament::Equal,
+         degrees: Temperament::Equal.degrees(),
+      }
+   }
+}
+
+impl Tuning {
+   /// Equal temperament with A4 tuned to `reference` Hz.
+   ///
+   /// Fails with [`Error::InvalidParameter`] if `reference` is not finite and positive.
+   pub fn new(reference: f64) -> Result<Self, Error> {
+      Self::with_temperament(reference, Temperament::Equal, 0)
+   }
+
+   /// A tuning in the given temperament, starting at the pitch class `root`,
+   /// i.e. `0` for C up to `11` for B, with A4 tuned to `reference` Hz.
+   ///
+   /// Fails with [`Error::InvalidParameter`] if `reference` is not finite and
+   /// positive, or if the degrees of a custom temperament are not strictly increasing.
+   pub fn with_temperament(
+      reference: f64,
+      temperament: Temperament,
+      root: u8,
+   ) -> Result<Self, Error> {
+      if !reference.is_finite() || reference <= 0.0 {
+         return Err(Error::InvalidParameter("reference pitch"));
+      }
+      if !temperament.is_valid() {
+         return Err(Error::InvalidParameter("temperament"));
+      }
+
+      Ok(Self {
+         reference,
+         root: root % 12,
+         degrees: temperament.degrees(),
+         temperament,
+      })
+   }
+
+   /// The frequency of A4.
+   pub fn reference(&self) -> f64 {
+      self.reference
+   }
+
+   pub fn temperament(&self) -> &Temperament {
+      &self.temperament
+   }
+
+   /// The pitch class, the temperament starts at.
+   pub fn root(&self) -> u8 {
+      self.root
+   }
+
+   /// The cents of the note `offset` semitones above A4, relative to the root below A4.
+   ///
+   /// Returns NaN if `offset` is out of range.
+   fn cents(&self, offset: i64) -> f64 {
+      let steps = self.degrees.len() as i64 - 1;
+      let period = self.degrees[steps as usize];
+
+      // Number of keys from the root to A4
+      let root_to_a = (9 - self.root as i64).rem_euclid(12);
+      let keys = match root_to_a.checked_add(offset) {
+         Some(keys) => keys,
+         None => return f64::NAN,
+      };
+
+      keys.div_euclid(steps) as f64 * period + self.degrees[keys.rem_euclid(steps) as usize]
+   }
+
+   /// The frequency of the note `offset` semitones above A4.
+   pub(crate) fn offset_frequency(&self, offset: i64) -> f64 {
+      let cents = self.cents(offset) - self.cents(0);
+      self.reference * f64::powf(2.0, cents / 1200.0)
+   }
+
+   /// Like [`notes::note_to_frequency`](crate::notes::note_to_frequency),
+   /// but in this tuning.
+   ///
+   /// Fractional notes are interpolated in cents between the neighbouring notes.
+   /// Returns NaN if `note` is not finite or out of range.
+   pub fn note_to_frequency(&self, note: f64) -> f64 {
+      let lower = note.floor();
+      // The upper bound is 2^63, which is one more than the largest i64
+      if !(i64::MIN as f64..i64::MAX as f64).contains(&lower) {
+         return f64::NAN;
+      }
+
+      let frac = note - lower;
+      let lower = lower as i64;
+      let low = self.offset_frequency(lower);
+      let high = lower
+         .checked_add(1)
+         .map_or(f64::NAN, |upper| self.offset_frequency(upper));
+      low * f64::powf(high / low, frac)
+   }
+
+   /// Like [`notes::frequency_to_approx_note`](crate::notes::frequency_to_approx_note),
+   /// but in this tuning.
+   ///
+   /// Returns NaN if `frequency` is not finite and positive.
+   pub fn frequency_to_approx_note(&self, frequency: f64) -> f64 {
+      if !frequency.is_finite() || frequency <= 0.0 {
+         return f64::NAN;
+      }
+
+      // Start from a guess with evenly spaced degrees and search the enclosing notes
+      let steps = (self.degrees.len() - 1) as f64;
+      let period = self.degrees[self.degrees.len() - 1];
+      let cents = ratio_to_cents(frequency / self.reference);
+      let mut lower = (cents * steps / period).floor() as i64;
+      while self.offset_frequency(lower) > frequency {
+         lower -= 1;
+      }
+      while self.offset_frequency(lower + 1) <= frequency {
+         lower += 1;
+      }
+
+      let low = self.offset_frequency(lower);
+      let high = self.offset_frequency(lower + 1);
+      lower as f64 + f64::ln(frequency / low) / f64::ln(high / low)
+   }
+
+   /// The frequency of `note` in this tuning.
+   pub fn frequency(&self, note: &Note) -> f64 {
+      self.offset_frequency(note.value() as i64)
+   }
+
+   /// Find the nearest note to `frequency` and the deviation from it in cents.
+   pub fn nearest_note(&self, frequency: f64) -> Result<(Note, f64), Error> {
+      let (note, _) = Note::from_approx(self.frequency_to_approx_note(frequency))?;
+      let cents = 1200.0 * f64::log2(frequency / self.frequency(&note));
+      Ok((note, cents))
+   }
+}
+
+fn ratio_to_cents(ratio: f64) -> f64 {
+   1200.0 * f64::log2(ratio)
+}
+
+/// Build the twelve degrees from a chain of fifths, starting `start` fifths below the root.
+fn chain_of_fifths(fifth: f64, start: i64) -> Vec<f64> {
+   let mut degrees = vec![0.0; 12];
+   for k in start..start + 12 {
+      let pitch_class = (7 * k).rem_euclid(12) as usize;
+      degrees[pitch_class] = (k as f64 * fifth).rem_euclid(1200.0);
+   }
+   degrees
+}
+
+/// Parse a pitch of a Scala file, which are cents if they contain a dot,
+/// and ratios otherwise.
+fn parse_scala_pitch(pitch: &str) -> Result<f64, Error> {
+   if pitch.contains('.') {
+      return pitch.parse::<f64>().map_err(|_| Error::InvalidScala);
+   }
+
+   let mut parts = pitch.splitn(2, '/');
+   let num = parts.next().unwrap_or("").parse::<f64>();
+   let den = parts.next().unwrap_or("1").parse::<f64>();
+   match (num, den) {
+      (Ok(num), Ok(den)) if num > 0.0 && den > 0.0 => Ok(ratio_to_cents(num / den)),
+      _ => Err(Error::InvalidScala),
+   }
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::notes::note_to_frequency;
+
+   fn note(name: &str) -> Note {
+      name.parse().unwrap()
+   }
+
+   #[test]
+   fn equal_temperament_matches_default() {
+      let tuning = Tuning::default();
+      for offset in -30..30 {
+         let expected = 440.0 * 2.0f64.powf(offset as f64 / 12.0);
+         assert!((tuning.note_to_frequency(offset as f64) - expected).abs() < 1e-9);
+         assert!((tuning.frequency_to_approx_note(expected) - offset as f64).abs() < 1e-9);
+      }
+   }
+
+   #[test]
+   fn reference_pitch() {
+      let tuning = Tuning::new(415.0).unwrap();
+      assert!((tuning.frequency(&note("A3")) - 207.5).abs() < 1e-9);
+
+      let (nearest, cents) = tuning.nearest_note(440.0).unwrap();
+      assert_eq!(nearest, note("A#4"));
+      assert!((cents - 1.27).abs() < 0.01);
+   }
+
+   #[test]
+   fn just_intonation() {
+      // Just intonation in C, with A4 at 440Hz
+      let tuning = Tuning::with_temperament(440.0, Temperament::JustIntonation, 0).unwrap();
+      let c = tuning.frequency(&note("C4"));
+      assert!((tuning.frequency(&note("A4")) - 440.0).abs() < 1e-9);
+      assert!((tuning.frequency(&note("E4")) / c - 1.25).abs() < 1e-9);
+      assert!((tuning.frequency(&note("G4")) / c - 1.5).abs() < 1e-9);
+      assert!((tuning.frequency(&note("C5")) / c - 2.0).abs() < 1e-9);
+
+      let approx = tuning.frequency_to_approx_note(tuning.frequency(&note("E5")));
+      assert!((approx - 7.0).abs() < 1e-9);
+   }
+
+   #[test]
+   fn meantone_thirds_are_pure() {
+      let tuning = Tuning::with_temperament(440.0, Temperament::Meantone, 0).unwrap();
+      let ratio = tuning.frequency(&note("E4")) / tuning.frequency(&note("C4"));
+      assert!((ratio - 1.25).abs() < 1e-9);
+
+      let tuning = Tuning::with_temperament(440.0, Temperament::Pythagorean, 0).unwrap();
+      let ratio = tuning.frequency(&note("G4")) / tuning.frequency(&note("C4"));
+      assert!((ratio - 1.5).abs() < 1e-9);
+   }
+
+   #[test]
+   fn scala_import() {
+      let scl = "! test.scl\n\
+                 !\n\
+                 Pentatonic test scale\n \
+                 5\n\
+                 !\n\
+                 200.0\n\
+                 6/5\n\
+                 3/2\n\
+                 900.0 sixth\n\
+                 2\n";
+      let temperament = Temperament::from_scala(scl).unwrap();
+      let tuning = Tuning::with_temperament(440.0, temperament, 9).unwrap();
+
+      // Five keys span an octave, starting at A4
+      assert!((tuning.note_to_frequency(5.0) - 880.0).abs() < 1e-9);
+      assert!((tuning.note_to_frequency(3.0) - 660.0).abs() < 1e-9);
+      assert!((tuning.note_to_frequency(-5.0) - 220.0).abs() < 1e-9);
+
+      assert_eq!(
+         Temperament::from_scala("x\n3\n1.0\n"),
+         Err(Error::InvalidScala)
+      );
+      assert_eq!(
+         Temperament::from_scala("x\n1\nabc\n"),
+         Err(Error::InvalidScala)
+      );
+      assert_eq!(
+         Temperament::from_scala("x\n3\n700.0\n500.0\n1200.0\n"),
+         Err(Error::InvalidScala)
+      );
+      assert_eq!(
+         Temperament::from_scala("x\n2\n-100.0\n1200.0\n"),
+         Err(Error::InvalidScala)
+      );
+   }
+
+   #[test]
+   fn invalid_tunings() {
+      for degrees in [
+         vec![],
+         vec![0.0, 1200.0],
+         vec![-100.0],
+         vec![700.0, 500.0, 1200.0],
+         vec![500.0, 500.0, 1200.0],
+         vec![f64::NAN, 1200.0],
+      ] {
+         assert_eq!(
+            Tuning::with_temperament(440.0, Temperament::Custom(degrees), 0),
+            Err(Error::InvalidParameter("temperament"))
+         );
+      }
+
+      for reference in [0.0, -440.0, f64::NAN, f64::INFINITY] {
+         assert_eq!(
+            Tuning::new(reference),
+            Err(Error::InvalidParameter("reference pitch"))
+         );
+      }
+   }
+
+   #[test]
+   fn invalid_frequencies() {
+      let custom = Temperament::Custom(vec![1.0, 2.0, 3.0]);
+      for tuning in [
+         Tuning::default(),
+         Tuning::with_temperament(440.0, custom, 0).unwrap(),
+      ] {
+         for frequency in [0.0, -440.0, f64::NAN, f64::INFINITY] {
+            assert!(tuning.frequency_to_approx_note(frequency).is_nan());
+         }
+         assert!(tuning.nearest_note(0.0).is_err());
+         for note in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e300, -1e300] {
+            assert!(tuning.note_to_frequency(note).is_nan());
+         }
+      }
+      for note in [f64::NAN, f64::INFINITY, 1e300] {
+         assert!(note_to_frequency(note).is_nan());
+      }
+   }
+}
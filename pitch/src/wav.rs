@@ -0,0 +1,735 @@
+//! Reading and writing WAV files, and running a [`Processor`] over them.
+//!
+//! Integer PCM with 8, 16, 24 or 32 bits and IEEE float with 32 or 64 bits
+//! are supported, with any number of channels.
+//! Samples are converted to and from `f32` or `f64` in the range from `-1.0` to `1.0`.
+
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use std::{
+   fs::File,
+   io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+   path::Path,
+};
+
+use crate::{cast, to_f64, Error, Processor, Sample};
+
+/// The number of frames read, processed and written at once by [`process`].
+const BLOCK_FRAMES: usize = 4096;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+/// The size of the largest `fmt ` chunk, that of the extensible format.
+const FMT_MAX_LEN: usize = 40;
+
+/// How the samples of a WAV file are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+   /// Signed integers, or unsigned ones for 8 bits.
+   Int,
+   /// IEEE floating point numbers.
+   Float,
+}
+
+/// The format of a WAV file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WavSpec {
+   pub channels: usize,
+   pub sample_rate: usize,
+   pub bits_per_sample: u16,
+   pub format: SampleFormat,
+}
+
+impl WavSpec {
+   /// The number of bytes per sample of a single channel.
+   fn bytes_per_sample(&self) -> usize {
+      usize::from(self.bits_per_sample / 8)
+   }
+
+   /// The number of bytes of all channels of a frame.
+   fn block_align(&self) -> usize {
+      self.channels * self.bytes_per_sample()
+   }
+
+   fn check(&self) -> Result<(), Error> {
+      let supported = match self.format {
+         SampleFormat::Int => [8, 16, 24, 32].contains(&self.bits_per_sample),
+         SampleFormat::Float => [32, 64].contains(&self.bits_per_sample),
+      };
+      if !supported
+         || self.channels == 0
+         || self.channels > usize::from(u16::MAX)
+         || self.sample_rate == 0
+         || self.sample_rate > u32::MAX as usize
+      {
+         return Err(Error::InvalidWav);
+      }
+
+      // The header stores the bytes per frame in 16 bits and per second in 32 bits
+      if self.block_align() > usize::from(u16::MAX) {
+         return Err(Error::InvalidParameter("block align"));
+      }
+      if self.sample_rate as u64 * self.block_align() as u64 > u64::from(u32::MAX) {
+         return Err(Error::InvalidParameter("byte rate"));
+      }
+
+      Ok(())
+   }
+
+   /// Decode a single sample from its little endian `bytes`.
+   fn decode(&self, bytes: &[u8]) -> f64 {
+      match (self.format, self.bits_per_sample) {
+         (SampleFormat::Int, 8) => (f64::from(bytes[0]) - 128.0) / 128.0,
+         (SampleFormat::Int, 16) => f64::from(i16::from_le_bytes([bytes[0], bytes[1]])) / 32768.0,
+         (SampleFormat::Int, 24) => {
+            // Sign extend by placing the bytes into the upper part of an i32
+            let x = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+            f64::from(x) / 8388608.0
+         }
+         (SampleFormat::Int, _) => {
+            f64::from(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])) / 2147483648.0
+         }
+         (SampleFormat::Float, 32) => {
+            f64::from(f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+         }
+         (SampleFormat::Float, _) => {
+            let mut le = [0; 8];
+            le.copy_from_slice(&bytes[..8]);
+            f64::from_le_bytes(le)
+         }
+      }
+   }
+
+   /// Encode `x` as little endian bytes into `bytes`, clipping integer samples.
+   fn encode(&self, x: f64, bytes: &mut [u8]) {
+      let int = |bits: u16| {
+         let scale = (1u64 << (bits - 1)) as f64;
+         (x * scale).round().clamp(-scale, scale - 1.0) as i32
+      };
+
+      match (self.format, self.bits_per_sample) {
+         (SampleFormat::Int, 8) => bytes[0] = (int(8) + 128) as u8,
+         (SampleFormat::Int, 16) => bytes.copy_from_slice(&(int(16) as i16).to_le_bytes()),
+         (SampleFormat::Int, 24) => bytes.copy_from_slice(&int(24).to_le_bytes()[..3]),
+         (SampleFormat::Int, _) => bytes.copy_from_slice(&int(32).to_le_bytes()),
+         (SampleFormat::Float, 32) => bytes.copy_from_slice(&(x as f32).to_le_bytes()),
+         (SampleFormat::Float, _) => bytes.copy_from_slice(&x.to_le_bytes()),
+      }
+   }
+}
+
+/// Reads the samples of a WAV file block by block.
+pub struct WavReader<R> {
+   inner: R,
+   spec: WavSpec,
+   /// The number of bytes of the data chunk not read yet.
+   remaining: u64,
+   bytes: Vec<u8>,
+}
+
+impl WavReader<BufReader<File>> {
+   /// Open the WAV file at `path`.
+   pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+      Self::new(BufReader::new(File::open(path)?))
+   }
+}
+
+impl<R: Read> WavReader<R> {
+   /// Read the header from `inner`, leaving it at the start of the samples.
+   pub fn new(mut inner: R) -> Result<Self, Error> {
+      let mut header = [0; 12];
+      inner.read_exact(&mut header)?;
+      if &header[..4] != b"RIFF" || &header[8..] != b"WAVE" {
+         return Err(Error::InvalidWav);
+      }
+
+      let mut spec = None;
+      loop {
+         let mut chunk = [0; 8];
+         inner.read_exact(&mut chunk)?;
+         let len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+
+         match &chunk[..4] {
+            b"fmt " => {
+               // Only the known fields are read, whatever the chunk claims its length to be
+               let mut fmt = [0; FMT_MAX_LEN];
+               let known = usize::min(len as usize, FMT_MAX_LEN);
+               inner.read_exact(&mut fmt[..known])?;
+               skip(
+                  &mut inner,
+                  u64::from(len) - known as u64 + u64::from(len % 2),
+               )?;
+               spec = Some(parse_fmt(&fmt[..known])?);
+            }
+            b"data" => {
+               let spec = spec.ok_or(Error::InvalidWav)?;
+               return Ok(Self {
+                  inner,
+                  spec,
+                  remaining: u64::from(len),
+                  bytes: vec![],
+               });
+            }
+            // Chunks are padded to an even length
+            _ => skip(&mut inner, u64::from(len) + u64::from(len % 2))?,
+         }
+      }
+   }
+
+   pub fn spec(&self) -> WavSpec {
+      self.spec
+   }
+
+   /// The number of frames, i.e. samples per channel, not read yet.
+   pub fn remaining_frames(&self) -> usize {
+      (self.remaining / self.spec.block_align() as u64) as usize
+   }
+
+   /// Read interleaved samples into `samples` and return how many were read.
+   ///
+   /// Only whole frames are read, and `0` is returned at the end of the file.
+   pub fn read<T: Sample>(&mut self, samples: &mut [T]) -> Result<usize, Error> {
+      let frames = usize::min(samples.len() / self.spec.channels, self.remaining_frames());
+      let bytes_per_sample = self.spec.bytes_per_sample();
+      self.bytes.resize(frames * self.spec.block_align(), 0);
+
+      // A truncated file ends after the last whole frame
+      let mut read = 0;
+      while read < self.bytes.len() {
+         match self.inner.read(&mut self.bytes[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err.into()),
+         }
+      }
+      let read = read - read % self.spec.block_align();
+      self.remaining = if read < self.bytes.len() {
+         0
+      } else {
+         self.remaining - read as u64
+      };
+
+      let count = read / bytes_per_sample;
+      for (x, bytes) in samples[..count]
+         .iter_mut()
+         .zip(self.bytes.chunks_exact(bytes_per_sample))
+      {
+         *x = cast(self.spec.decode(bytes));
+      }
+      Ok(count)
+   }
+}
+
+/// Parse the contents of a `fmt ` chunk.
+fn parse_fmt(fmt: &[u8]) -> Result<WavSpec, Error> {
+   if fmt.len() < 16 {
+      return Err(Error::InvalidWav);
+   }
+   let u16_at = |i: usize| u16::from_le_bytes([fmt[i], fmt[i + 1]]);
+
+   // The extensible format keeps the actual format in the start of the sub format GUID
+   let tag = match u16_at(0) {
+      FORMAT_EXTENSIBLE if fmt.len() >= 26 => u16_at(24),
+      tag => tag,
+   };
+   let format = match tag {
+      FORMAT_PCM => SampleFormat::Int,
+      FORMAT_FLOAT => SampleFormat::Float,
+      _ => return Err(Error::InvalidWav),
+   };
+
+   let spec = WavSpec {
+      channels: usize::from(u16_at(2)),
+      sample_rate: u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]) as usize,
+      bits_per_sample: u16_at(14),
+      format,
+   };
+   spec.check().map_err(|_| Error::InvalidWav)?;
+   if usize::from(u16_at(12)) != spec.block_align() {
+      return Err(Error::InvalidWav);
+   }
+
+   Ok(spec)
+}
+
+/// Skip `len` bytes of `inner`.
+fn skip<R: Read>(inner: &mut R, len: u64) -> Result<(), Error> {
+   let skipped = std::io::copy(&mut inner.take(len), &mut std::io::sink())?;
+   if skipped < len {
+      return Err(Error::Io(std::io::ErrorKind::UnexpectedEof));
+   }
+   Ok(())
+}
+
+/// Writes samples into a WAV file.
+///
+/// The sizes in the header are only filled in by [`WavWriter::finalize`],
+/// which needs to be called after the last sample.
+pub struct WavWriter<W: Write + Seek> {
+   inner: W,
+   spec: WavSpec,
+   /// The number of bytes of samples written.
+   written: u64,
+   bytes: Vec<u8>,
+}
+
+impl WavWriter<BufWriter<File>> {
+   /// Create a WAV file at `path`, replacing any existing file.
+   pub fn create<P: AsRef<Path>>(path: P, spec: WavSpec) -> Result<Self, Error> {
+      Self::new(BufWriter::new(File::create(path)?), spec)
+   }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+   /// Write the header into `inner`.
+   pub fn new(mut inner: W, spec: WavSpec) -> Result<Self, Error> {
+      spec.check()?;
+
+      let tag = match spec.format {
+         SampleFormat::Int => FORMAT_PCM,
+         SampleFormat::Float => FORMAT_FLOAT,
+      };
+      let block_align = spec.block_align() as u16;
+
+      let mut header = Vec::with_capacity(44);
+      header.extend_from_slice(b"RIFF");
+      header.extend_from_slice(&0u32.to_le_bytes());
+      header.extend_from_slice(b"WAVEfmt ");
+      header.extend_from_slice(&16u32.to_le_bytes());
+      header.extend_from_slice(&tag.to_le_bytes());
+      header.extend_from_slice(&(spec.channels as u16).to_le_bytes());
+      header.extend_from_slice(&(spec.sample_rate as u32).to_le_bytes());
+      header.extend_from_slice(&((spec.sample_rate * spec.block_align()) as u32).to_le_bytes());
+      header.extend_from_slice(&block_align.to_le_bytes());
+      header.extend_from_slice(&spec.bits_per_sample.to_le_bytes());
+      header.extend_from_slice(b"data");
+      header.extend_from_slice(&0u32.to_le_bytes());
+      inner.write_all(&header)?;
+
+      Ok(Self {
+         inner,
+         spec,
+         written: 0,
+         bytes: vec![],
+      })
+   }
+
+   pub fn spec(&self) -> WavSpec {
+      self.spec
+   }
+
+   /// Write interleaved samples, which should contain whole frames.
+   pub fn write<T: Sample>(&mut self, samples: &[T]) -> Result<(), Error> {
+      let bytes_per_sample = self.spec.bytes_per_sample();
+      self.bytes.resize(samples.len() * bytes_per_sample, 0);
+      for (x, bytes) in samples
+         .iter()
+         .zip(self.bytes.chunks_exact_mut(bytes_per_sample))
+      {
+         self.spec.encode(to_f64(*x), bytes);
+      }
+
+      self.inner.write_all(&self.bytes)?;
+      self.written += self.bytes.len() as u64;
+      Ok(())
+   }
+
+   /// Fill in the sizes in the header and return the inner writer.
+   ///
+   /// Fails if more than 4 GiB of samples were written, which WAV can not describe.
+   pub fn finalize(mut self) -> Result<W, Error> {
+      let pad = self.written % 2;
+      if pad == 1 {
+         self.inner.write_all(&[0])?;
+      }
+
+      let data_len = u32::try_from(self.written).map_err(|_| Error::InvalidWav)?;
+      let riff_len = u32::try_from(36 + self.written + pad).map_err(|_| Error::InvalidWav)?;
+      self.inner.seek(SeekFrom::Start(4))?;
+      self.inner.write_all(&riff_len.to_le_bytes())?;
+      self.inner.seek(SeekFrom::Start(40))?;
+      self.inner.write_all(&data_len.to_le_bytes())?;
+      self.inner.seek(SeekFrom::End(0))?;
+      self.inner.flush()?;
+
+      Ok(self.inner)
+   }
+}
+
+/// Stream all samples of `reader` through `processor` into `writer`.
+///
+/// The output is aligned with the input by dropping the first `latency`
+/// frames of the output and flushing the processor with silence at the end,
+/// so the output has exactly as many frames as the input.
+/// Only a block of samples is held in memory at any time.
+pub fn process<T, R, W, P>(
+   reader: &mut WavReader<R>,
+   writer: &mut WavWriter<W>,
+   processor: &mut P,
+) -> Result<(), Error>
+where
+   T: Sample,
+   R: Read,
+   W: Write + Seek,
+   P: Processor<T> + ?Sized,
+{
+   let channels = reader.spec().channels;
+   if processor.channels() != channels {
+      return Err(Error::ChannelCount {
+         expected: processor.channels(),
+         actual: channels,
+      });
+   }
+   if writer.spec().channels != channels {
+      return Err(Error::ChannelCount {
+         expected: channels,
+         actual: writer.spec().channels,
+      });
+   }
+   if processor.sample_rate() != reader.spec().sample_rate {
+      return Err(Error::SampleRateMismatch {
+         expected: processor.sample_rate(),
+         actual: reader.spec().sample_rate,
+      });
+   }
+
+   let mut input = vec![T::zero(); BLOCK_FRAMES * channels];
+   let mut output = vec![T::zero(); BLOCK_FRAMES * channels];
+   // The output frames still to drop, and the frames of silence still to feed
+   let mut skip = processor.latency();
+   let mut flush = processor.latency();
+
+   loop {
+      let mut len = reader.read(&mut input)?;
+      if len == 0 {
+         if flush == 0 {
+            break;
+         }
+         let frames = usize::min(flush, BLOCK_FRAMES);
+         flush -= frames;
+         len = frames * channels;
+         input[..len].iter_mut().for_each(|x| *x = T::zero());
+      }
+
+      processor.try_process(&input[..len], &mut output[..len])?;
+
+      let dropped = usize::min(skip, len / channels);
+      skip -= dropped;
+      writer.write(&output[dropped * channels..len])?;
+   }
+
+   Ok(())
+}
+
+/// Process the WAV file at `input` with `processor` into a new WAV file at
+/// `output`, using [`process`].
+///
+/// The output has the same format as the input.
+pub fn process_file<T: Sample, P: Processor<T> + ?Sized>(
+   input: impl AsRef<Path>,
+   output: impl AsRef<Path>,
+   processor: &mut P,
+) -> Result<(), Error> {
+   let mut reader = WavReader::open(input)?;
+   let mut writer = WavWriter::create(output, reader.spec())?;
+   process(&mut reader, &mut writer, processor)?;
+   writer.finalize()?;
+   Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+   use crate::{FrameContext, SpectralProcessor, Wavelet};
+   use core::f64::consts::PI;
+   use std::io::Cursor;
+
+   fn identity(_: &mut Wavelet, _: &FrameContext) {}
+
+   fn sine(frames: usize, channels: usize) -> Vec<f64> {
+      (0..frames * channels)
+         .map(|n| {
+            let (frame, channel) = (n / channels, n % channels);
+            0.8 * f64::sin(2.0 * PI * (440.0 + 110.0 * channel as f64) * frame as f64 / 48000.0)
+         })
+         .collect()
+   }
+
+   fn write(spec: WavSpec, samples: &[f64]) -> Vec<u8> {
+      let mut writer = WavWriter::new(Cursor::new(vec![]), spec).unwrap();
+      writer.write(samples).unwrap();
+      writer.finalize().unwrap().into_inner()
+   }
+
+   #[test]
+   fn roundtrip() {
+      let formats = [
+         (SampleFormat::Int, 8, 1.0 / 128.0),
+         (SampleFormat::Int, 16, 1.0 / 32768.0),
+         (SampleFormat::Int, 24, 1.0 / 8388608.0),
+         (SampleFormat::Int, 32, 1.0 / 2147483648.0),
+         (SampleFormat::Float, 32, 1e-7),
+         (SampleFormat::Float, 64, 0.0),
+      ];
+
+      for &channels in [1, 2].iter() {
+         for &(format, bits_per_sample, tolerance) in formats.iter() {
+            let spec = WavSpec {
+               channels,
+               sample_rate: 48000,
+               bits_per_sample,
+               format,
+            };
+            // An odd number of 8 bit mono samples needs a padding byte
+            let samples = sine(1001, channels);
+            let bytes = write(spec, &samples);
+            assert_eq!(bytes.len() % 2, 0);
+
+            let mut reader = WavReader::new(Cursor::new(bytes)).unwrap();
+            assert_eq!(reader.spec(), spec);
+            assert_eq!(reader.remaining_frames(), 1001);
+
+            // Read in blocks that are not a multiple of the channels
+            let mut read = vec![];
+            let mut block = [0.0; 301];
+            loop {
+               let len = reader.read(&mut block).unwrap();
+               if len == 0 {
+                  break;
+               }
+               read.extend_from_slice(&block[..len]);
+            }
+
+            assert_eq!(read.len(), samples.len());
+            for (a, b) in read.iter().zip(samples.iter()) {
+               assert!(
+                  (a - b).abs() <= tolerance,
+                  "{:?} {}",
+                  format,
+                  bits_per_sample
+               );
+            }
+         }
+      }
+   }
+
+   #[test]
+   fn integers_clip() {
+      let spec = WavSpec {
+         channels: 1,
+         sample_rate: 8000,
+         bits_per_sample: 16,
+         format: SampleFormat::Int,
+      };
+      let bytes = write(spec, &[2.0, -2.0]);
+      assert_eq!(&bytes[44..], &[0xff, 0x7f, 0x00, 0x80]);
+   }
+
+   #[test]
+   fn skips_unknown_chunks() {
+      let spec = WavSpec {
+         channels: 2,
+         sample_rate: 44100,
+         bits_per_sample: 16,
+         format: SampleFormat::Int,
+      };
+      let bytes = write(spec, &[0.5, -0.5]);
+
+      // Insert an odd sized chunk between the format and the data
+      let mut patched = bytes[..36].to_vec();
+      patched.extend_from_slice(b"LIST\x03\x00\x00\x00abc\x00");
+      patched.extend_from_slice(&bytes[36..]);
+
+      let mut reader = WavReader::new(Cursor::new(patched)).unwrap();
+      let mut samples = [0.0; 4];
+      assert_eq!(reader.read(&mut samples).unwrap(), 2);
+      assert_eq!(&samples[..2], &[0.5, -0.5]);
+   }
+
+   #[test]
+   fn invalid_files() {
+      assert_eq!(
+         WavReader::new(Cursor::new(b"RIFF\x00\x00\x00\x00AVI ".to_vec())).err(),
+         Some(Error::InvalidWav)
+      );
+      assert_eq!(
+         WavReader::new(Cursor::new(b"RIFF".to_vec())).err(),
+         Some(Error::Io(std::io::ErrorKind::UnexpectedEof))
+      );
+
+      let spec = WavSpec {
+         channels: 1,
+         sample_rate: 48000,
+         bits_per_sample: 12,
+         format: SampleFormat::Int,
+      };
+      assert_eq!(
+         WavWriter::new(Cursor::new(vec![]), spec).err(),
+         Some(Error::InvalidWav)
+      );
+
+      // Specs that do not fit the header
+      let spec = WavSpec {
+         channels: 20000,
+         sample_rate: 48000,
+         bits_per_sample: 32,
+         format: SampleFormat::Float,
+      };
+      assert_eq!(
+         WavWriter::new(Cursor::new(vec![]), spec).err(),
+         Some(Error::InvalidParameter("block align"))
+      );
+      let spec = WavSpec {
+         channels: 8000,
+         sample_rate: 96000,
+         bits_per_sample: 64,
+         format: SampleFormat::Float,
+      };
+      assert_eq!(
+         WavWriter::new(Cursor::new(vec![]), spec).err(),
+         Some(Error::InvalidParameter("byte rate"))
+      );
+
+      // Reading such a header is an invalid file
+      let spec = WavSpec {
+         channels: 1,
+         sample_rate: 48000,
+         bits_per_sample: 32,
+         format: SampleFormat::Float,
+      };
+      let mut bytes = write(spec, &sine(100, 1));
+      bytes[22..24].copy_from_slice(&20000u16.to_le_bytes());
+      assert_eq!(
+         WavReader::new(Cursor::new(bytes)).err(),
+         Some(Error::InvalidWav)
+      );
+
+      // A huge fmt chunk is not allocated, but runs into the end of the file
+      let mut bytes = b"RIFF\x00\x00\x00\x00WAVEfmt \xf0\xff\xff\xff".to_vec();
+      bytes.extend_from_slice(&[1, 0, 1, 0, 0x80, 0xbb, 0, 0, 0, 0x77, 1, 0, 2, 0, 16, 0]);
+      assert_eq!(
+         WavReader::new(Cursor::new(bytes)).err(),
+         Some(Error::Io(std::io::ErrorKind::UnexpectedEof))
+      );
+   }
+
+   #[test]
+   fn long_fmt_chunk() {
+      let spec = WavSpec {
+         channels: 1,
+         sample_rate: 48000,
+         bits_per_sample: 16,
+         format: SampleFormat::Int,
+      };
+      let bytes = write(spec, &sine(100, 1));
+
+      // Append unknown fields to the fmt chunk
+      let mut long = bytes[..16].to_vec();
+      long.extend_from_slice(&(16u32 + 50).to_le_bytes());
+      long.extend_from_slice(&bytes[20..36]);
+      long.extend_from_slice(&[0xaa; 50]);
+      long.extend_from_slice(&bytes[36..]);
+
+      let mut reader = WavReader::new(Cursor::new(long)).unwrap();
+      assert_eq!(reader.spec(), spec);
+      let mut samples = vec![0.0; 200];
+      assert_eq!(reader.read(&mut samples).unwrap(), 100);
+   }
+
+   #[test]
+   fn process_keeps_length_and_tail() {
+      let spec = WavSpec {
+         channels: 1,
+         sample_rate: 48000,
+         bits_per_sample: 32,
+         format: SampleFormat::Float,
+      };
+      // Not a multiple of the block or step size
+      let samples = sine(10000, 1);
+      let mut reader = WavReader::new(Cursor::new(write(spec, &samples))).unwrap();
+      let mut writer = WavWriter::new(Cursor::new(vec![]), spec).unwrap();
+
+      let mut processor = SpectralProcessor::new(48000, 1024, 256, identity).unwrap();
+      process(&mut reader, &mut writer, &mut processor).unwrap();
+
+      let bytes = writer.finalize().unwrap().into_inner();
+      let mut reader = WavReader::new(Cursor::new(bytes)).unwrap();
+      let mut output = vec![0.0; 20000];
+      assert_eq!(reader.read(&mut output).unwrap(), samples.len());
+
+      // Aligned with the input, up to its very end
+      for (x, y) in samples.iter().zip(output.iter()) {
+         assert!((x - y).abs() < 1e-3, "{} != {}", x, y);
+      }
+   }
+
+   #[test]
+   fn process_into_other_channel_count() {
+      let spec = WavSpec {
+         channels: 1,
+         sample_rate: 48000,
+         bits_per_sample: 16,
+         format: SampleFormat::Int,
+      };
+      let mut reader = WavReader::new(Cursor::new(write(spec, &sine(100, 1)))).unwrap();
+      let stereo = WavSpec {
+         channels: 2,
+         ..spec
+      };
+      let mut writer = WavWriter::new(Cursor::new(vec![]), stereo).unwrap();
+
+      let mut processor = SpectralProcessor::new(48000, 1024, 256, identity).unwrap();
+      assert_eq!(
+         process(&mut reader, &mut writer, &mut processor).err(),
+         Some(Error::ChannelCount {
+            expected: 1,
+            actual: 2
+         })
+      );
+   }
+
+   #[test]
+   fn process_stereo_file() {
+      let dir = std::env::temp_dir();
+      let input = dir.join("pitch-process-stereo-in.wav");
+      let output = dir.join("pitch-process-stereo-out.wav");
+
+      let spec = WavSpec {
+         channels: 2,
+         sample_rate: 48000,
+         bits_per_sample: 24,
+         format: SampleFormat::Int,
+      };
+      let samples = sine(6000, 2);
+      let mut writer = WavWriter::create(&input, spec).unwrap();
+      writer.write(&samples).unwrap();
+      writer.finalize().unwrap();
+
+      // A mono processor does not fit a stereo file
+      let mut mono = SpectralProcessor::new(48000, 1024, 256, identity).unwrap();
+      assert_eq!(
+         process_file(&input, &output, &mut mono).err(),
+         Some(Error::ChannelCount {
+            expected: 1,
+            actual: 2
+         })
+      );
+
+      let mut stereo = crate::MultiChannelProcessor::new(48000, 1024, 256, 2, identity).unwrap();
+      process_file(&input, &output, &mut stereo).unwrap();
+
+      let mut reader = WavReader::open(&output).unwrap();
+      assert_eq!(reader.spec(), spec);
+      let mut processed = vec![0.0; 20000];
+      assert_eq!(reader.read(&mut processed).unwrap(), samples.len());
+      for (x, y) in samples.iter().zip(processed.iter()) {
+         assert!((x - y).abs() < 1e-3, "{} != {}", x, y);
+      }
+
+      std::fs::remove_file(input).unwrap();
+      std::fs::remove_file(output).unwrap();
+   }
+}
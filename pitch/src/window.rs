@@ -0,0 +1,175 @@
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::f64::consts::PI;
+
+/// The window function applied to every frame before analysis and after
+/// synthesis.
+///
+/// The window is chosen when constructing a [`Frequencer`](crate::Frequencer)
+/// or [`Resynth`](crate::Resynth) and precomputed into a table once.
+/// All windows are periodic, i.e. they are meant to be overlapped.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Window {
+   /// No windowing at all.
+   Rectangular,
+   /// The raised cosine window. Good allround choice.
+   #[default]
+   Hann,
+   /// Like [`Window::Hann`] but with a lower first sidelobe.
+   Hamming,
+   /// Three term Blackman window.
+   Blackman,
+   /// Four term Blackman-Harris window, very low leakage.
+   BlackmanHarris,
+   /// Kaiser window with the given `beta`.
+   /// Higher `beta` trades resolution for less leakage.
+   Kaiser(f64),
+   /// Flat top window, very accurate amplitudes but poor resolution.
+   FlatTop,
+}
+
+impl Window {
+   /// Compute the value of the window at position `k` of a frame of size `len`.
+   pub fn value(&self, k: usize, len: usize) -> f64 {
+      let x = 2.0 * PI * k as f64 / len as f64;
+      match *self {
+         Window::Rectangular => 1.0,
+         Window::Hann => cosine_sum(x, &[0.5, 0.5]),
+         Window::Hamming => cosine_sum(x, &[0.54, 0.46]),
+         Window::Blackman => cosine_sum(x, &[0.42, 0.5, 0.08]),
+         Window::BlackmanHarris => cosine_sum(x, &[0.35875, 0.48829, 0.14128, 0.01168]),
+         Window::Kaiser(beta) => {
+            let r = 2.0 * k as f64 / len as f64 - 1.0;
+            bessel_i0(beta * f64::sqrt(1.0 - r * r)) / bessel_i0(beta)
+         }
+         Window::FlatTop => cosine_sum(
+            x,
+            &[
+               0.21557895,
+               0.41663158,
+               0.277263158,
+               0.083578947,
+               0.006947368,
+            ],
+         ),
+      }
+   }
+
+   /// Precompute the window for a frame of size `len`.
+   #[cfg(feature = "alloc")]
+   pub fn table(&self, len: usize) -> Vec<f64> {
+      (0..len).map(|k| self.value(k, len)).collect()
+   }
+}
+
+/// Computes the gain introduced by windowing a frame twice (analysis and
+/// synthesis) and overlap-adding it with the given `step_size`, at `position`
+/// within a step.
+///
+/// The synthesized output needs to be divided by this value to get back
+/// to the original amplitude.
+/// Only few windows overlap to a constant gain, so this is needed for every position.
+pub(crate) fn overlap_gain<'a>(
+   table: impl IntoIterator<Item = &'a f64>,
+   step_size: usize,
+   position: usize,
+) -> f64 {
+   table
+      .into_iter()
+      .skip(position)
+      .step_by(step_size)
+      .map(|w| w * w)
+      .sum()
+}
+
+/// The factor to normalize the overlap-added output by, at `position` within a step.
+///
+/// Positions that no window reaches are silenced.
+pub(crate) fn overlap_normalization<'a>(
+   table: impl IntoIterator<Item = &'a f64>,
+   step_size: usize,
+   position: usize,
+) -> f64 {
+   let gain = overlap_gain(table, step_size, position);
+   if gain > f64::EPSILON {
+      1.0 / gain
+   } else {
+      0.0
+   }
+}
+
+/// Evaluate a generalized cosine window with alternating signs.
+fn cosine_sum(x: f64, coeffs: &[f64]) -> f64 {
+   coeffs
+      .iter()
+      .enumerate()
+      .map(|(i, a)| {
+         let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+         sign * a * f64::cos(i as f64 * x)
+      })
+      .sum()
+}
+
+/// Zeroth order modified bessel function of the first kind.
+fn bessel_i0(x: f64) -> f64 {
+   let mut sum = 1.0;
+   let mut term = 1.0;
+   let mut k = 1.0;
+
+   // The series converges quickly for the betas used in practice
+   while term > sum * 1e-12 {
+      term *= (x / (2.0 * k)) * (x / (2.0 * k));
+      sum += term;
+      k += 1.0;
+   }
+
+   sum
+}
+
+#[cfg(test)]
+mod tests {
+   use super::*;
+
+   #[test]
+   fn hann_matches_inline() {
+      let table = Window::Hann.table(64);
+      for (k, w) in table.iter().enumerate() {
+         let expected = -0.5 * f64::cos(2.0 * PI * k as f64 / 64.0) + 0.5;
+         assert!((w - expected).abs() < 1e-12);
+      }
+   }
+
+   #[test]
+   fn windows_peak_in_center() {
+      for window in [
+         Window::Hann,
+         Window::Hamming,
+         Window::Blackman,
+         Window::BlackmanHarris,
+         Window::Kaiser(8.0),
+         Window::FlatTop,
+      ]
+      .iter()
+      {
+         let table = window.table(256);
+         assert!((table[128] - 1.0).abs() < 1e-3, "{:?}", window);
+         assert!(table[0] < 0.1, "{:?}", window);
+      }
+   }
+
+   #[test]
+   fn hann_overlap_gain() {
+      // Hann squared at 75% overlap sums to 1.5
+      let table = Window::Hann.table(2048);
+      for position in 0..512 {
+         assert!((overlap_gain(&table, 512, position) - 1.5).abs() < 1e-9);
+      }
+
+      // But not at a step, that does not divide the frame
+      let gains = (0..500)
+         .map(|position| overlap_gain(&table, 500, position))
+         .collect::<Vec<_>>();
+      assert!(gains.iter().any(|gain| (gain - 1.5).abs() > 1e-3));
+      assert!(gains.iter().all(|gain| *gain > 1.0));
+   }
+}